@@ -0,0 +1,65 @@
+//! Settings read from a json file, for example:
+//! { "server": "http://localhost:9663", "socket_server": "ws://localhost:9664",
+//!   "token_command": "pass lichess.org/token" }
+//! Command line options takes precedence over these
+
+use serde_derive::Deserialize;
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub server: Option<String>,
+    pub socket_server: Option<String>,
+    pub token_file: Option<String>,
+    pub token_command: Option<String>,
+}
+
+impl Config {
+    /// $XDG_CONFIG_HOME/liru/config.json, or ~/.config/liru/config.json
+    pub fn default_path() -> Option<PathBuf> {
+        env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+            .map(|dir| dir.join("liru").join("config.json"))
+    }
+
+    /// Reads the config, a missing file is only
+    /// an error when `required` is set
+    pub fn load(path: &Path, required: bool) -> Result<Config, String> {
+        match fs::read_to_string(path) {
+            Ok(data) => serde_json::from_str(&data)
+                .map_err(|e| format!("Invalid config {}: {}", path.display(), e)),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound && !required => {
+                Ok(Config::default())
+            },
+            Err(e) => Err(format!("Could not read config {}: {}", path.display(), e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn load_missing() {
+        let path = Path::new("/nonexistent/liru/config.json");
+        assert!(Config::load(path, false).unwrap().server.is_none());
+        assert!(Config::load(path, true).is_err());
+    }
+
+    #[test]
+    fn parse() {
+        let config: Config = serde_json::from_str(
+            r#"{ "server": "http://localhost:9663", "socket_server": "ws://localhost:9664" }"#).unwrap();
+        assert_eq!(config.server.as_deref(), Some("http://localhost:9663"));
+        assert_eq!(config.socket_server.as_deref(), Some("ws://localhost:9664"));
+        assert!(serde_json::from_str::<Config>(r#"{ "sever": "x" }"#).is_err());
+    }
+}
@@ -1,20 +1,30 @@
 use serde_derive::Deserialize;
 
-mod socket;
+pub mod socket;
+mod ackable;
+mod action;
+mod board;
 mod latency_recorder;
-mod clock;
+pub mod clock;
 mod connected_pov;
 mod color;
 mod crowd;
 mod lila_message;
+pub mod movegen;
 mod pov;
+pub mod san;
+mod variant;
 
 pub use crate::game::latency_recorder::LatencyRecorder;
-pub use crate::game::connected_pov::ConnectedPov;
+pub use crate::game::action::Action;
+pub use crate::game::board::{Board, Pockets, Role, Square};
+pub use crate::game::connected_pov::{ConnectedPov, Notice, Premove};
 pub use crate::game::color::Color;
 pub use crate::game::pov::{Pov,Player};
-use crate::game::clock::Clock;
+pub use crate::game::lila_message::{Dests, EndData, Offers};
+pub use crate::game::clock::Clock;
 pub use crate::game::crowd::Crowd;
+pub use crate::game::variant::{Variant, CHECKS_TO_WIN};
 
 #[allow(non_snake_case)]
 #[derive(Deserialize)]
@@ -34,19 +44,111 @@ pub struct Game {
     pub threefold: Option<bool>,
     pub source: String,
     pub status: Status,
+    pub winner: Option<Color>,
+    /// Id of the rematch game, once it's started
+    pub rematch: Option<String>,
     //createdAt: 1655254531882,
 }
 
-#[derive(Deserialize)]
-pub struct Variant {
-    pub key: String,
-    pub name: String,
-    pub short: String,
-}
-
-#[derive(Deserialize)]
+#[derive(Deserialize, Debug)]
 pub struct Status {
     pub id: i64,
     pub name: String,
 }
 
+/// Name of the piece a pawn can promote to, by its letter,
+/// kings are only promoted to in antichess
+pub fn promotion_role(letter: char) -> Option<&'static str> {
+    Role::from_char(letter).filter(|&r| r != Role::Pawn).map(Role::name)
+}
+
+/// How a finished game ended, for display
+#[derive(Debug, PartialEq)]
+pub struct Outcome {
+    /// 1-0, 0-1, ½-½, or * when aborted
+    pub score: &'static str,
+    pub result: String,
+    pub reason: String,
+}
+
+impl Status {
+    /// Statuses from aborted (25) and up are all ways a game can end,
+    /// created (10) and started (20) are still going
+    pub fn finished(&self) -> bool {
+        self.id >= 25
+    }
+
+    /// When the game ended by other means than the status says
+    pub fn unknown_finish() -> Status {
+        Status { id: 38, name: "unknownFinish".into() }
+    }
+
+    pub fn outcome(&self, winner: Option<Color>) -> Option<Outcome> {
+        if !self.finished() {
+            return None;
+        }
+        let loser = winner.map(|w| (!w).name());
+        let reason = match (self.name.as_str(), loser) {
+            ("aborted", _) => "Game aborted".to_owned(),
+            ("mate", _) => "Checkmate".to_owned(),
+            ("resign", Some(loser)) => format!("{} resigned", loser),
+            ("stalemate", _) => "Stalemate".to_owned(),
+            ("timeout", Some(loser)) => format!("{} left the game", loser),
+            ("draw", _) => "Draw".to_owned(),
+            ("outoftime", Some(_)) => "Time out".to_owned(),
+            ("outoftime", None) => "Time out, insufficient material".to_owned(),
+            ("cheat", _) => "Cheat detected".to_owned(),
+            ("noStart", Some(loser)) => format!("{} didn't move", loser),
+            ("variantEnd", _) => "Variant ending".to_owned(),
+            (name, _) => name.to_owned(),
+        };
+        let (score, result) = match (self.name.as_str(), winner) {
+            ("aborted", _) => ("*", "No result".to_owned()),
+            (_, Some(Color::white)) => ("1-0", "White is victorious".to_owned()),
+            (_, Some(Color::black)) => ("0-1", "Black is victorious".to_owned()),
+            (_, None) => ("½-½", "Draw".to_owned()),
+        };
+        Some(Outcome { score, result, reason })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn status_finished() {
+        let status = |id, name: &str| Status { id, name: name.into() };
+        assert!(!status(10, "created").finished());
+        assert!(!status(20, "started").finished());
+        assert!(status(25, "aborted").finished());
+        assert!(status(30, "mate").finished());
+        assert!(status(60, "variantEnd").finished());
+    }
+
+    #[test]
+    fn promotion_roles() {
+        assert_eq!(promotion_role('q'), Some("queen"));
+        assert_eq!(promotion_role('N'), Some("knight"));
+        assert_eq!(promotion_role('p'), None);
+    }
+
+    #[test]
+    fn status_outcome() {
+        let status = |id, name: &str| Status { id, name: name.into() };
+        assert_eq!(status(20, "started").outcome(None), None);
+        let mate = status(30, "mate").outcome(Some(Color::black)).unwrap();
+        assert_eq!(mate.score, "0-1");
+        assert_eq!(mate.result, "Black is victorious");
+        assert_eq!(mate.reason, "Checkmate");
+        let resign = status(31, "resign").outcome(Some(Color::white)).unwrap();
+        assert_eq!((resign.score, resign.reason.as_str()), ("1-0", "Black resigned"));
+        let draw = status(34, "draw").outcome(None).unwrap();
+        assert_eq!((draw.score, draw.result.as_str()), ("½-½", "Draw"));
+        let aborted = status(25, "aborted").outcome(None).unwrap();
+        assert_eq!((aborted.score, aborted.reason.as_str()), ("*", "Game aborted"));
+        let flagged = status(35, "outoftime").outcome(None).unwrap();
+        assert_eq!(flagged.reason, "Time out, insufficient material");
+    }
+}
+
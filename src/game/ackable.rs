@@ -0,0 +1,148 @@
+use std::time::{Duration, Instant};
+
+/// Resend unacknowledged messages this often
+const RESEND_AFTER: Duration = Duration::from_millis(1200);
+/// Give up on messages that aren't acknowledged by then
+const GIVE_UP_AFTER: Duration = Duration::from_secs(10);
+
+/// Keeps track of sent messages that lila should acknowledge,
+/// identified by the `a` field in the message data
+pub struct Ackable {
+    current_id: u64,
+    pending: Vec<Pending>,
+}
+
+pub struct Pending {
+    pub id: u64,
+    /// What it was about, like the uci of a move
+    pub subject: String,
+    pub message: String,
+    first_sent: Instant,
+    last_sent: Instant,
+}
+
+impl Ackable {
+    pub fn new() -> Ackable {
+        Ackable {
+            current_id: 0,
+            pending: vec!(),
+        }
+    }
+
+    pub fn next_id(&mut self) -> u64 {
+        self.current_id += 1;
+        self.current_id
+    }
+
+    pub fn register(&mut self, id: u64, subject: String, message: String) {
+        let now = Instant::now();
+        self.pending.push(Pending {
+            id,
+            subject,
+            message,
+            first_sent: now,
+            last_sent: now,
+        });
+    }
+
+    /// Without id, like older lila versions, everything is acknowledged
+    pub fn ack(&mut self, id: Option<u64>) {
+        match id {
+            Some(id) => self.pending.retain(|p| p.id != id),
+            None => self.pending.clear(),
+        }
+    }
+
+    /// Acknowledges by subject, for when the result is seen
+    /// before, or without, the acknowledgement
+    pub fn ack_subject(&mut self, subject: &str) {
+        self.pending.retain(|p| p.subject != subject);
+    }
+
+    /// Removes and returns everything pending, for when lila rejected it
+    pub fn reject(&mut self) -> Vec<Pending> {
+        self.pending.drain(..).collect()
+    }
+
+    /// Messages due to be resent, assuming they will be
+    pub fn resend(&mut self, now: Instant) -> Vec<String> {
+        self.pending.iter_mut()
+            .filter(|p| now.duration_since(p.last_sent) >= RESEND_AFTER)
+            .map(|p| {
+                p.last_sent = now;
+                p.message.clone()
+            })
+            .collect()
+    }
+
+    /// Removes and returns messages that are too old to resend
+    pub fn expired(&mut self, now: Instant) -> Vec<Pending> {
+        let (expired, pending) = self.pending.drain(..)
+            .partition(|p| now.duration_since(p.first_sent) >= GIVE_UP_AFTER);
+        self.pending = pending;
+        expired
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn ack_removes() {
+        let mut subject = Ackable::new();
+        let a = subject.next_id();
+        let b = subject.next_id();
+        assert_eq!((a, b), (1, 2));
+        subject.register(a, "e2e4".into(), "1".into());
+        subject.register(b, "d2d4".into(), "2".into());
+        subject.ack(Some(a));
+        assert_eq!(subject.reject().iter().map(|p| p.id).collect::<Vec<_>>(), vec!(b));
+        assert!(subject.is_empty());
+    }
+
+    #[test]
+    fn ack_without_id() {
+        let mut subject = Ackable::new();
+        subject.register(1, "e2e4".into(), "1".into());
+        subject.register(2, "d2d4".into(), "2".into());
+        subject.ack(None);
+        assert!(subject.is_empty());
+    }
+
+    #[test]
+    fn ack_subject() {
+        let mut subject = Ackable::new();
+        subject.register(1, "e2e4".into(), "1".into());
+        subject.ack_subject("d2d4");
+        assert!(!subject.is_empty());
+        subject.ack_subject("e2e4");
+        assert!(subject.is_empty());
+    }
+
+    #[test]
+    fn resend_when_due() {
+        let mut subject = Ackable::new();
+        subject.register(1, "e2e4".into(), "1".into());
+        let now = Instant::now();
+        assert!(subject.resend(now).is_empty());
+        assert_eq!(subject.resend(now + RESEND_AFTER), vec!("1"));
+        assert!(subject.resend(now + RESEND_AFTER).is_empty());
+        assert_eq!(subject.resend(now + RESEND_AFTER * 2), vec!("1"));
+    }
+
+    #[test]
+    fn expired_gives_up() {
+        let mut subject = Ackable::new();
+        subject.register(1, "e2e4".into(), "1".into());
+        let now = Instant::now();
+        assert!(subject.expired(now).is_empty());
+        let expired = subject.expired(now + GIVE_UP_AFTER);
+        assert_eq!(expired[0].subject, "e2e4");
+        assert!(subject.is_empty());
+    }
+}
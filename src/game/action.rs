@@ -0,0 +1,76 @@
+use serde_derive::Serialize;
+
+/// Things a player can do in a game besides moving
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Action {
+    Abort,
+    Resign,
+    /// Offers a draw, or accepts the opponent's offer
+    DrawYes,
+    DrawNo,
+    /// Proposes a takeback, or accepts the opponent's proposal
+    TakebackYes,
+    TakebackNo,
+    /// Gives the opponent 15 more seconds
+    Moretime,
+    /// Claims victory when the opponent is gone
+    ResignForce,
+    RematchYes,
+    RematchNo,
+}
+
+#[derive(Serialize, Debug)]
+struct ActionPacket {
+    t: &'static str,
+}
+
+impl Action {
+    /// The socket message type lila expects
+    pub fn packet_type(self) -> &'static str {
+        match self {
+            Action::Abort => "abort",
+            Action::Resign => "resign",
+            Action::DrawYes => "draw-yes",
+            Action::DrawNo => "draw-no",
+            Action::TakebackYes => "takeback-yes",
+            Action::TakebackNo => "takeback-no",
+            Action::Moretime => "moretime",
+            Action::ResignForce => "resign-force",
+            Action::RematchYes => "rematch-yes",
+            Action::RematchNo => "rematch-no",
+        }
+    }
+
+    /// What the player is asked to confirm
+    pub fn question(self) -> &'static str {
+        match self {
+            Action::Abort => "Abort the game?",
+            Action::Resign => "Resign?",
+            Action::DrawYes => "Offer or accept a draw?",
+            Action::DrawNo => "Decline the draw?",
+            Action::TakebackYes => "Propose or accept a takeback?",
+            Action::TakebackNo => "Decline the takeback?",
+            Action::Moretime => "Give 15 seconds?",
+            Action::ResignForce => "Claim victory?",
+            Action::RematchYes => "Offer or accept a rematch?",
+            Action::RematchNo => "Decline the rematch?",
+        }
+    }
+
+    pub fn to_message(self) -> String {
+        serde_json::to_string(&ActionPacket { t: self.packet_type() }).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn to_message() {
+        assert_eq!(Action::Resign.to_message(), r#"{"t":"resign"}"#);
+        assert_eq!(Action::DrawYes.to_message(), r#"{"t":"draw-yes"}"#);
+        assert_eq!(Action::ResignForce.to_message(), r#"{"t":"resign-force"}"#);
+        assert_eq!(Action::RematchNo.to_message(), r#"{"t":"rematch-no"}"#);
+    }
+}
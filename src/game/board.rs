@@ -0,0 +1,434 @@
+//! The position of a game, parsed from and written back to FEN,
+//! including the pockets of crazyhouse
+
+use std::fmt;
+
+use super::Color;
+
+/// A square like e4, with files and ranks counted from 0
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Square {
+    pub file: u8,
+    pub rank: u8,
+}
+
+impl Square {
+    pub fn new(file: u8, rank: u8) -> Square {
+        Square { file, rank }
+    }
+
+    pub fn parse(name: &str) -> Option<Square> {
+        match name.as_bytes() {
+            &[file @ b'a'..=b'h', rank @ b'1'..=b'8'] => Some(Square::new(file - b'a', rank - b'1')),
+            _ => None,
+        }
+    }
+
+    /// All squares, from a1 to h8 rank by rank
+    pub fn all() -> impl Iterator<Item = Square> {
+        (0..64).map(|i| Square::new(i % 8, i / 8))
+    }
+
+    pub fn offset(self, files: i8, ranks: i8) -> Option<Square> {
+        let file = self.file as i8 + files;
+        let rank = self.rank as i8 + ranks;
+        if (0..8).contains(&file) && (0..8).contains(&rank) {
+            Some(Square::new(file as u8, rank as u8))
+        } else {
+            None
+        }
+    }
+
+    fn index(self) -> usize {
+        self.rank as usize * 8 + self.file as usize
+    }
+}
+
+impl fmt::Display for Square {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}", (b'a' + self.file) as char, self.rank + 1)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Role {
+    Pawn,
+    Knight,
+    Bishop,
+    Rook,
+    Queen,
+    King,
+}
+
+impl Role {
+    pub fn from_char(c: char) -> Option<Role> {
+        match c.to_ascii_lowercase() {
+            'p' => Some(Role::Pawn),
+            'n' => Some(Role::Knight),
+            'b' => Some(Role::Bishop),
+            'r' => Some(Role::Rook),
+            'q' => Some(Role::Queen),
+            'k' => Some(Role::King),
+            _ => None,
+        }
+    }
+
+    /// Lowercase letter, as for black in FEN
+    pub fn char(self) -> char {
+        match self {
+            Role::Pawn => 'p',
+            Role::Knight => 'n',
+            Role::Bishop => 'b',
+            Role::Rook => 'r',
+            Role::Queen => 'q',
+            Role::King => 'k',
+        }
+    }
+
+    /// As lila names them
+    pub fn name(self) -> &'static str {
+        match self {
+            Role::Pawn => "pawn",
+            Role::Knight => "knight",
+            Role::Bishop => "bishop",
+            Role::Rook => "rook",
+            Role::Queen => "queen",
+            Role::King => "king",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Piece {
+    pub color: Color,
+    pub role: Role,
+    /// A promoted pawn in crazyhouse, which goes back to the pocket as a pawn
+    pub promoted: bool,
+}
+
+impl Piece {
+    pub fn new(color: Color, role: Role) -> Piece {
+        Piece { color, role, promoted: false }
+    }
+
+    pub fn from_char(c: char) -> Option<Piece> {
+        let color = if c.is_ascii_uppercase() { Color::white } else { Color::black };
+        Role::from_char(c).map(|role| Piece::new(color, role))
+    }
+
+    /// Uppercase for white, as in FEN
+    #[allow(dead_code)]
+    pub fn char(self) -> char {
+        match self.color {
+            Color::white => self.role.char().to_ascii_uppercase(),
+            Color::black => self.role.char(),
+        }
+    }
+}
+
+/// Captured pieces in crazyhouse, in the order they were taken
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Pockets {
+    pub white: Vec<Role>,
+    pub black: Vec<Role>,
+}
+
+impl Pockets {
+    pub fn of(&self, color: Color) -> &Vec<Role> {
+        match color {
+            Color::white => &self.white,
+            Color::black => &self.black,
+        }
+    }
+
+    pub fn count(&self, color: Color, role: Role) -> usize {
+        self.of(color).iter().filter(|&&r| r == role).count()
+    }
+
+    fn parse(pieces: &str) -> Result<Pockets, String> {
+        let mut pockets = Pockets::default();
+        for c in pieces.chars() {
+            let piece = Piece::from_char(c).ok_or_else(|| format!("Unknown piece in pocket: {}", c))?;
+            match piece.color {
+                Color::white => pockets.white.push(piece.role),
+                Color::black => pockets.black.push(piece.role),
+            }
+        }
+        Ok(pockets)
+    }
+
+    fn to_fen(&self) -> String {
+        let white = self.white.iter().map(|r| r.char().to_ascii_uppercase());
+        let black = self.black.iter().map(|r| r.char());
+        white.chain(black).collect()
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Board {
+    squares: [Option<Piece>; 64],
+    pub turn: Color,
+    /// Castling rights as in FEN, KQkq or the rook files of Shredder-FEN,
+    /// empty when there are none
+    pub castling: String,
+    pub en_passant: Option<Square>,
+    pub halfmoves: u32,
+    pub fullmoves: u32,
+    /// Only in crazyhouse
+    pub pockets: Option<Pockets>,
+}
+
+impl Board {
+    /// Reads a full FEN, or just its piece placement, in which case the rest
+    /// is as for a new game. Crazyhouse pockets may follow the ranks, like
+    /// `.../RNBQKBNR/Qn` as lila writes them, or `.../RNBQKBNR[Qn]`.
+    pub fn from_fen(fen: &str) -> Result<Board, String> {
+        let mut fields = fen.split_whitespace();
+        let placement = fields.next().ok_or("Empty fen")?;
+        let (placement, pockets) = match placement.find('[') {
+            Some(i) => (&placement[..i], Some(placement[i + 1..].trim_end_matches(']'))),
+            None => (placement, None),
+        };
+        let rows: Vec<&str> = placement.split('/').collect();
+        let pockets = match (rows.len(), pockets) {
+            (8, None) => None,
+            (8, Some(pieces)) => Some(Pockets::parse(pieces)?),
+            (9, None) => Some(Pockets::parse(rows[8])?),
+            _ => return Err(format!("Expected 8 ranks in fen: {}", fen)),
+        };
+        let mut squares: [Option<Piece>; 64] = [None; 64];
+        for (i, row) in rows.iter().take(8).enumerate() {
+            let rank = 7 - i as u8;
+            let mut file = 0;
+            for c in row.chars() {
+                if c == '~' {
+                    let last = Square::new(file.max(1) - 1, rank);
+                    match squares[last.index()].as_mut() {
+                        Some(piece) => piece.promoted = true,
+                        None => return Err(format!("Promoted marker without a piece: {}", row)),
+                    }
+                    continue;
+                }
+                if let Some(empty) = c.to_digit(10) {
+                    file += empty as u8;
+                    continue;
+                }
+                let piece = Piece::from_char(c).ok_or_else(|| format!("Unknown piece: {}", c))?;
+                if file >= 8 {
+                    return Err(format!("Too many squares in rank: {}", row));
+                }
+                squares[Square::new(file, rank).index()] = Some(piece);
+                file += 1;
+            }
+            if file != 8 {
+                return Err(format!("Expected 8 squares in rank: {}", row));
+            }
+        }
+        let turn = match fields.next() {
+            None | Some("w") => Color::white,
+            Some("b") => Color::black,
+            Some(other) => return Err(format!("Unknown side to move: {}", other)),
+        };
+        let castling = match fields.next() {
+            None | Some("-") => String::new(),
+            Some(rights) if rights.chars().all(|c| "KQkqABCDEFGHabcdefgh".contains(c)) => rights.to_owned(),
+            Some(other) => return Err(format!("Invalid castling rights: {}", other)),
+        };
+        let en_passant = match fields.next() {
+            None | Some("-") => None,
+            Some(square) => Some(Square::parse(square).ok_or_else(|| format!("Invalid en passant: {}", square))?),
+        };
+        let halfmoves = fields.next().map_or(Ok(0), |n| n.parse()).map_err(|_| "Invalid halfmove clock")?;
+        let fullmoves = fields.next().map_or(Ok(1), |n| n.parse()).map_err(|_| "Invalid move number")?;
+        Ok(Board { squares, turn, castling, en_passant, halfmoves, fullmoves, pockets })
+    }
+
+    /// Piece placement only, like lila sends with moves
+    #[allow(dead_code)]
+    pub fn board_fen(&self) -> String {
+        let mut fen = String::new();
+        for rank in (0..8).rev() {
+            let mut empty = 0;
+            for file in 0..8 {
+                match self.get(Square::new(file, rank)) {
+                    Some(piece) => {
+                        if empty > 0 {
+                            fen.push_str(&empty.to_string());
+                            empty = 0;
+                        }
+                        fen.push(piece.char());
+                        if piece.promoted {
+                            fen.push('~');
+                        }
+                    },
+                    None => empty += 1,
+                }
+            }
+            if empty > 0 {
+                fen.push_str(&empty.to_string());
+            }
+            if rank > 0 {
+                fen.push('/');
+            }
+        }
+        if let Some(pockets) = &self.pockets {
+            fen.push('/');
+            fen.push_str(&pockets.to_fen());
+        }
+        fen
+    }
+
+    #[allow(dead_code)]
+    pub fn to_fen(&self) -> String {
+        format!("{} {} {} {} {} {}",
+                self.board_fen(),
+                if self.turn == Color::white { "w" } else { "b" },
+                if self.castling.is_empty() { "-" } else { &self.castling },
+                self.en_passant.map_or("-".to_owned(), |s| s.to_string()),
+                self.halfmoves,
+                self.fullmoves)
+    }
+
+    pub fn get(&self, square: Square) -> Option<Piece> {
+        self.squares[square.index()]
+    }
+
+    pub fn set(&mut self, square: Square, piece: Option<Piece>) {
+        self.squares[square.index()] = piece;
+    }
+
+    /// Where the king of the color is, if there is exactly one
+    pub fn king(&self, color: Color) -> Option<Square> {
+        let mut kings = Square::all()
+            .filter(|&s| self.get(s) == Some(Piece::new(color, Role::King)));
+        match (kings.next(), kings.next()) {
+            (Some(king), None) => Some(king),
+            _ => None,
+        }
+    }
+
+    /// Guesses castling rights from where kings and rooks stand,
+    /// for when only the piece placement is known
+    pub fn infer_castling(&mut self) {
+        let mut rights = String::new();
+        for &color in &[Color::white, Color::black] {
+            let back = if color == Color::white { 0 } else { 7 };
+            let king = match self.king(color) {
+                Some(king) if king.rank == back => king,
+                _ => continue,
+            };
+            let rook = Piece::new(color, Role::Rook);
+            let rooks: Vec<u8> = (0..8).filter(|&f| self.get(Square::new(f, back)) == Some(rook)).collect();
+            let mut side = String::new();
+            if rooks.iter().any(|&f| f > king.file) {
+                side.push('K');
+            }
+            if rooks.iter().any(|&f| f < king.file) {
+                side.push('Q');
+            }
+            if color == Color::black {
+                side = side.to_ascii_lowercase();
+            }
+            rights.push_str(&side);
+        }
+        self.castling = rights;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const START: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+    #[test]
+    fn squares() {
+        assert_eq!(Square::parse("a1"), Some(Square::new(0, 0)));
+        assert_eq!(Square::parse("h8"), Some(Square::new(7, 7)));
+        assert_eq!(Square::parse("e4").unwrap().to_string(), "e4");
+        assert_eq!(Square::parse("i1"), None);
+        assert_eq!(Square::parse("a0"), None);
+        assert_eq!(Square::parse("a10"), None);
+        assert_eq!(Square::new(0, 0).offset(-1, 0), None);
+        assert_eq!(Square::new(0, 0).offset(1, 2), Square::parse("b3"));
+    }
+
+    #[test]
+    fn start_position() {
+        let board = Board::from_fen(START).unwrap();
+        assert_eq!(board.get(Square::parse("e1").unwrap()), Some(Piece::new(Color::white, Role::King)));
+        assert_eq!(board.get(Square::parse("d8").unwrap()), Some(Piece::new(Color::black, Role::Queen)));
+        assert_eq!(board.get(Square::parse("e4").unwrap()), None);
+        assert_eq!(board.turn, Color::white);
+        assert_eq!(board.castling, "KQkq");
+        assert_eq!(board.en_passant, None);
+        assert_eq!((board.halfmoves, board.fullmoves), (0, 1));
+        assert_eq!(board.pockets, None);
+        assert_eq!(board.king(Color::black), Square::parse("e8"));
+    }
+
+    #[test]
+    fn round_trip() {
+        for fen in &[
+            START,
+            "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+            "r1bqk2r/pppp1ppp/2n2n2/2b1p3/2B1P3/5N2/PPPP1PPP/RNBQ1RK1 b kq - 5 4",
+            "1r2k1r1/8/8/8/8/8/8/1R2K1R1 w GBgb - 0 1",
+            "8/8/8/8/8/8/8/8 b - - 99 150",
+            "r1bqk2r/ppp2ppp/2n5/3pp3/8/5N2/PPPP1PPP/R1BQKB1R/Pnb w KQkq - 0 11",
+            "rQ~bqk2r/ppp2ppp/8/8/8/8/PPP2PPP/R1BQKB1R/ w KQkq - 0 20",
+        ] {
+            assert_eq!(Board::from_fen(fen).unwrap().to_fen(), *fen);
+        }
+    }
+
+    #[test]
+    fn placement_only() {
+        let board = Board::from_fen("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR").unwrap();
+        assert_eq!(board.turn, Color::white);
+        assert_eq!(board.castling, "");
+        assert_eq!(board.to_fen(), "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR w - - 0 1");
+    }
+
+    #[test]
+    fn crazyhouse_pockets() {
+        let board = Board::from_fen("r1bqk2r/ppp2ppp/2n5/3pp3/8/5N2/PPPP1PPP/R1BQKB1R/PPnb w KQkq - 0 11").unwrap();
+        let pockets = board.pockets.as_ref().unwrap();
+        assert_eq!(pockets.white, vec!(Role::Pawn, Role::Pawn));
+        assert_eq!(pockets.black, vec!(Role::Knight, Role::Bishop));
+        assert_eq!(pockets.count(Color::white, Role::Pawn), 2);
+        assert_eq!(pockets.count(Color::black, Role::Queen), 0);
+        let brackets = Board::from_fen("r1bqk2r/ppp2ppp/2n5/3pp3/8/5N2/PPPP1PPP/R1BQKB1R[PPnb] w KQkq - 0 11").unwrap();
+        assert_eq!(brackets, board);
+        let empty = Board::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR/ w KQkq - 0 1").unwrap();
+        assert_eq!(empty.pockets, Some(Pockets::default()));
+    }
+
+    #[test]
+    fn promoted_pieces() {
+        let board = Board::from_fen("rQ~bqk2r/ppp2ppp/8/8/8/8/PPP2PPP/R1BQKB1R/ w KQkq - 0 20").unwrap();
+        let queen = board.get(Square::parse("b8").unwrap()).unwrap();
+        assert!(queen.promoted);
+        assert_eq!(queen.char(), 'Q');
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(Board::from_fen("").is_err());
+        assert!(Board::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP").is_err());
+        assert!(Board::from_fen("rnbqkbnr/pppppppp/9/8/8/8/PPPPPPPP/RNBQKBNR").is_err());
+        assert!(Board::from_fen("rnbqkbnr/ppppxppp/8/8/8/8/PPPPPPPP/RNBQKBNR").is_err());
+        assert!(Board::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR x").is_err());
+        assert!(Board::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq e9").is_err());
+    }
+
+    #[test]
+    fn infer_castling() {
+        let mut board = Board::from_fen("r3k2r/8/8/8/8/8/8/4K2R").unwrap();
+        board.infer_castling();
+        assert_eq!(board.castling, "Kkq");
+        let mut kings_moved = Board::from_fen("r6r/4k3/8/8/8/8/8/R3K2R").unwrap();
+        kings_moved.infer_castling();
+        assert_eq!(kings_moved.castling, "KQ");
+    }
+}
@@ -6,6 +6,7 @@ use super::Color;
 
 // TODO: ehm, maybe not like this
 // wrap to implement trait on foreign type in this version of rust
+#[derive(Debug)]
 pub struct Time(time::OffsetDateTime);
 impl Time {
     pub fn default() -> Time {
@@ -13,10 +14,20 @@ impl Time {
     }
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Debug)]
 pub struct Clock {
     pub white: f64,
     pub black: f64,
+    /// Only the side to move's time runs, and not before both have moved
+    #[serde(default)]
+    pub running: bool,
+    /// Time control in seconds, only in the game itself, not in its updates
+    #[serde(default)]
+    pub initial: u32,
+    #[serde(default)]
+    pub increment: u32,
+    /// Time is short below this many seconds
+    pub emerg: Option<u32>,
     #[serde(skip)]
     #[serde(default = "Time::default")]
     last_update: Time,
@@ -29,22 +40,105 @@ impl Clock {
             Color::black => self.black,
         }
     }
-    
-    //pub fn update(&mut self, white: f64, black: f64) {
-    //    self.last_update = Time(time::OffsetDateTime::now_utc());
-    //    self.white = white;
-    //    self.black = black;
-    //}
-    
+
+    /// Takes new times from lila, which took about half the
+    /// latency to arrive, so that much has passed already
+    pub fn update(&mut self, white: f64, black: f64, running: bool, latency_ms: i64) {
+        let now = time::OffsetDateTime::now_utc();
+        self.last_update = Time(now - time::Duration::milliseconds(latency_ms / 2));
+        self.white = white;
+        self.black = black;
+        self.running = running;
+    }
+
     pub fn tick(&mut self, color: Color) {
         let now = time::OffsetDateTime::now_utc();
         let Time(updated) = self.last_update;
         let passed = ((now - updated).whole_milliseconds() as f64) / 1000.0;
         self.last_update = Time(now);
+        if !self.running {
+            return;
+        }
         match color {
-            // TODO: only tick when gameIsRunning instead of using max
+            // ticking stops when the game ends, see Pov::tick
             Color::white => self.white = (self.white - passed).max(0.0),
             Color::black => self.black = (self.black - passed).max(0.0),
         };
     }
+
+    pub fn emergency(&self, color: Color) -> bool {
+        self.from(color) < self.emerg.unwrap_or(10) as f64
+    }
+
+    /// Like 3+2, in minutes and seconds of increment
+    pub fn time_control(&self) -> Option<String> {
+        let minutes = match self.initial {
+            0 if self.increment == 0 => return None,
+            15 => "¼".to_owned(),
+            30 => "½".to_owned(),
+            45 => "¾".to_owned(),
+            90 => "1.5".to_owned(),
+            initial => (initial / 60).to_string(),
+        };
+        Some(format!("{}+{}", minutes, self.increment))
+    }
+}
+
+/// Time left as mm:ss, with tenths below ten seconds
+pub fn format(seconds: f64) -> String {
+    let seconds = seconds.max(0.0);
+    let whole = seconds as u64;
+    if seconds < 10.0 {
+        let tenths = (seconds * 10.0) as u64 % 10;
+        format!("00:{:02}.{}", whole, tenths)
+    } else {
+        format!("{:02}:{:02}", whole / 60, whole % 60)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn clock(json: &str) -> Clock {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn formats() {
+        assert_eq!(format(180.0), "03:00");
+        assert_eq!(format(61.9), "01:01");
+        assert_eq!(format(59.99), "00:59");
+        assert_eq!(format(10.0), "00:10");
+        assert_eq!(format(9.96), "00:09.9");
+        assert_eq!(format(0.05), "00:00.0");
+        assert_eq!(format(-1.0), "00:00.0");
+        assert_eq!(format(5400.0), "90:00");
+    }
+
+    #[test]
+    fn config() {
+        let c = clock(r#"{"running":true,"initial":180,"increment":2,"white":178.5,"black":180,"emerg":30,"moretime":15}"#);
+        assert!(c.running);
+        assert_eq!(c.time_control().as_deref(), Some("3+2"));
+        assert!(!c.emergency(Color::white));
+        let update = clock(r#"{"white":58.42,"black":61.3}"#);
+        assert!(!update.running);
+        assert_eq!(update.time_control(), None);
+        let bullet = clock(r#"{"initial":30,"increment":0,"white":30,"black":30}"#);
+        assert_eq!(bullet.time_control().as_deref(), Some("½+0"));
+    }
+
+    #[test]
+    fn ticks_only_when_running() {
+        let mut c = clock(r#"{"white":60,"black":60}"#);
+        c.update(60.0, 60.0, false, 2000);
+        c.tick(Color::white);
+        assert_eq!(c.white, 60.0);
+        // half the latency is taken on the next tick
+        c.update(60.0, 60.0, true, 2000);
+        c.tick(Color::white);
+        assert!(c.white < 59.1 && c.white > 58.5, "{}", c.white);
+        assert_eq!(c.black, 60.0);
+    }
 }
@@ -10,6 +10,15 @@ pub enum Color {
     black,
 }
 
+impl Color {
+    pub fn name(self) -> &'static str {
+        match self {
+            Color::white => "White",
+            Color::black => "Black",
+        }
+    }
+}
+
 impl Not for Color {
     type Output = Color;
     fn not(self) -> Color {
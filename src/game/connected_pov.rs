@@ -1,15 +1,19 @@
 use serde_derive::Serialize;
 
 use std::sync::{Arc, Mutex};
-use std::sync::mpsc;
-use std::thread;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
 
 use serde_json;
+use tokio::sync::mpsc;
 use uuid::Uuid;
 
 use crate::lila;
-use crate::lila::Session;
+use crate::lila::LilaError;
 
+use super::ackable::Ackable;
+use super::Action;
+use super::board::Role;
 use super::LatencyRecorder;
 use super::Pov;
 use super::Color;
@@ -20,92 +24,366 @@ use crate::game::lila_message::LilaMessage;
 pub struct ConnectedPov {
     pub pov: Arc<Mutex<Pov>>,
     pub latency: Arc<Mutex<LatencyRecorder>>,
-    send_tx: mpsc::Sender<String>,
+    pub status: Arc<Mutex<socket::Status>>,
+    pub notice: Arc<Mutex<Option<Notice>>>,
+    ackable: Arc<Mutex<Ackable>>,
+    rejected: Arc<Mutex<Option<Rejected>>>,
+    premoves: Arc<Mutex<Vec<Premove>>>,
+    /// Dropping it closes the socket
+    send_tx: mpsc::UnboundedSender<String>,
+}
+
+/// A move made during the opponent's turn, sent once it is legal
+#[derive(Clone, Debug, PartialEq)]
+pub struct Premove {
+    pub from: String,
+    pub to: String,
+    /// The piece letter, like q for queen
+    pub promotion: Option<char>,
+    /// Only sent in reply to this opponent move, in uci
+    pub after: Option<String>,
+}
+
+impl Premove {
+    pub fn uci(&self) -> String {
+        format!("{}{}{}", self.from, self.to, self.promotion.map_or(String::new(), |p| p.to_string()))
+    }
+
+    /// The premove answering the opponent's move, a conditional one keyed
+    /// by that move comes before an unconditional one. The others are dropped.
+    fn reply(premoves: Vec<Premove>, opponent: &str) -> Option<Premove> {
+        let (conditional, unconditional): (Vec<_>, Vec<_>) = premoves.into_iter().partition(|p| p.after.is_some());
+        conditional.into_iter().find(|p| p.after.as_deref() == Some(opponent))
+            .or_else(|| unconditional.into_iter().next())
+    }
+
+    fn dest(self) -> Dest {
+        Dest {
+            from: self.from,
+            to: self.to,
+            promotion: self.promotion.and_then(super::promotion_role).map(|r| r.to_owned()),
+        }
+    }
+}
+
+/// A move that lila didn't accept
+pub struct Rejected {
+    pub uci: String,
+    pub reason: &'static str,
+}
+
+/// A short message about something that happened to the game
+pub struct Notice {
+    pub text: String,
+    at: Instant,
+}
+
+impl Notice {
+    pub fn new(text: &str) -> Notice {
+        Notice {
+            text: text.to_owned(),
+            at: Instant::now(),
+        }
+    }
+
+    pub fn visible(&self) -> bool {
+        self.at.elapsed() < Duration::from_secs(3)
+    }
 }
 
 impl ConnectedPov {
-    pub fn new(session: &lila::Session, path: &str) -> ConnectedPov {
-        let body = session.get(path);
+    pub async fn connect(session: &lila::Session, path: &str) -> Result<ConnectedPov, LilaError> {
+        let body = session.get_async(path).await?;
         log::debug!("GET response: {}", body);
-        let pov: Pov = serde_json::from_str(&body).unwrap();
-        let version = match pov.player.version {
-            Some(v) => v as u64,
-            None => 0
-        };
+        let pov: Pov = serde_json::from_str(&body)?;
+        let version = Arc::new(AtomicU64::new(pov.version()));
         let socket_path = pov.url.socket.clone();
         let pov_1 = Arc::new(Mutex::new(pov));
-        let (game_tx, game_rx) = mpsc::channel();
-        let (send_tx, send_rx) = mpsc::channel();
-        let send_rx = Arc::new(Mutex::new(send_rx));
+        let (send_tx, send_rx) = mpsc::unbounded_channel();
+        let status_1 = Arc::new(Mutex::new(socket::Status::Connecting));
 
         let pov_2 = pov_1.clone();
-        let c = session.cookie.clone();
         let sri = Uuid::new_v4();
         log::debug!("SRI set to {}", sri);
         let socket_path = str::replace(&socket_path, "/v1", "/v6");
-        let url = Session::socket_url(&format!("{}?sri={}&v={}", socket_path, sri, version));
-        thread::spawn(move || {
-            socket::Client::connect(&c, url.clone(), version, game_tx.clone(), send_rx);
-        });
+        let url = session.socket_url(&format!("{}?sri={}", socket_path, sri));
+        let mut socket = socket::Client::new(
+            (*session.cookie).clone(), url, version.clone(), send_rx, status_1.clone());
 
         let latency_1 = Arc::new(Mutex::new(LatencyRecorder::new()));
         let latency_2 = latency_1.clone();
-        thread::spawn(move || loop {
-            let obj = game_rx.recv().unwrap();
-            let mut pov = pov_2.lock().unwrap();
-            // log::debug!("{}", &obj);
-            match LilaMessage::decode(&obj) {
-                Some(LilaMessage::Pong(p)) => {
-                    latency_2.lock().unwrap().add(p.latency);
-                },
-                Some(LilaMessage::Move(m)) => {
-                    pov.game.fen = m.fen;
-                    pov.game.turns = m.ply;
-                    pov.game.player = if m.ply % 2 == 0 { Color::white } else { Color::black };
-                    pov.game.lastMove = Some(m.uci);
-                    pov.game.lastMoveSan = Some(m.san);
-                    if let Some(c) = m.clock {
-                        pov.clock = Some(c);
-                    };
-                },
-                Some(LilaMessage::Clock(c)) => {
-                    pov.clock = Some(c);
-                },
-                Some(LilaMessage::Crowd(c)) => {
-                    pov.crowd = Some(c);
-                },
-                //LilaMessage::End => tx_1.send(Message::close()).unwrap(),
-                _ => ()
-            };
+        let notice_1 = Arc::new(Mutex::new(None));
+        let notice_2 = notice_1.clone();
+        let ackable_1 = Arc::new(Mutex::new(Ackable::new()));
+        let ackable_2 = ackable_1.clone();
+        let rejected_1 = Arc::new(Mutex::new(None));
+        let rejected_2 = rejected_1.clone();
+        let premoves_1: Arc<Mutex<Vec<Premove>>> = Arc::new(Mutex::new(vec!()));
+        let premoves_2 = premoves_1.clone();
+        // weak so the socket still closes when the pov is dropped
+        let send_tx_2 = send_tx.downgrade();
+        let session = session.clone();
+        session.clone().spawn(async move {
+            // messages up to this version are already part of the pov
+            let mut synced = 0;
+            while let Some(event) = socket.next_event().await {
+                let obj = match event {
+                    socket::Event::Message(obj) => obj,
+                    socket::Event::Connected { reconnected: true } => {
+                        synced = ConnectedPov::resync(&session, &pov_2, &version).await.unwrap_or(synced);
+                        continue;
+                    },
+                    socket::Event::Gap => {
+                        if let Some(v) = ConnectedPov::resync(&session, &pov_2, &version).await {
+                            synced = v;
+                            *notice_2.lock().unwrap() = Some(Notice::new("Resynced"));
+                        }
+                        continue;
+                    },
+                    socket::Event::Connected { .. } | socket::Event::Disconnected => continue,
+                };
+                if matches!(obj.get("v").and_then(|v| v.as_u64()), Some(v) if v <= synced) {
+                    continue;
+                }
+                let message = LilaMessage::decode(&obj);
+                if let Some(LilaMessage::Resync) = message {
+                    let rejected = ackable_2.lock().unwrap().reject().pop();
+                    if let Some(rejected) = rejected {
+                        log::warn!("Move rejected: {}", rejected.subject);
+                        *rejected_2.lock().unwrap() = Some(Rejected {
+                            uci: rejected.subject,
+                            reason: "rejected",
+                        });
+                    }
+                    synced = ConnectedPov::resync(&session, &pov_2, &version).await.unwrap_or(synced);
+                    continue;
+                }
+                let mut pov = pov_2.lock().unwrap();
+                match message {
+                    Some(LilaMessage::Pong(p)) => {
+                        latency_2.lock().unwrap().add(p.latency);
+                    },
+                    Some(LilaMessage::Ack(id)) => {
+                        ackable_2.lock().unwrap().ack(id);
+                    },
+                    Some(LilaMessage::Move(m)) | Some(LilaMessage::Drop(m)) => {
+                        ackable_2.lock().unwrap().ack_subject(&m.uci);
+                        let before = pov.board().ok();
+                        pov.game.fen = m.fen;
+                        pov.game.turns = m.ply;
+                        pov.game.player = if m.ply % 2 == 0 { Color::white } else { Color::black };
+                        pov.game.lastMove = Some(m.uci);
+                        pov.game.lastMoveSan = Some(m.san);
+                        pov.possibleMoves = m.dests;
+                        pov.moved(before, m.check);
+                        if let Some(status) = m.status {
+                            pov.game.status = status;
+                            pov.game.winner = m.winner;
+                            // a game that just ended takes no premove
+                            if pov.game.status.finished() {
+                                premoves_2.lock().unwrap().clear();
+                            }
+                        }
+                        if let Some(c) = m.clock {
+                            pov.update_clock(c, latency_2.lock().unwrap().average());
+                        };
+                        // the echo of our own move leaves the premoves waiting
+                        let premove = match (pov.movable(), pov.game.lastMove.as_deref()) {
+                            (true, Some(last)) => Premove::reply(premoves_2.lock().unwrap().drain(..).collect(), last),
+                            _ => None,
+                        };
+                        match (premove, send_tx_2.upgrade()) {
+                            (Some(premove), Some(send_tx)) if pov.legal(&premove.from, &premove.to) => {
+                                log::info!("Sending premove {}", premove.uci());
+                                send_ackable(&ackable_2, &latency_2, &send_tx, "move", premove.uci(), premove.dest());
+                            },
+                            (Some(premove), _) => {
+                                let text = format!("Premove {} is not legal, dropped", premove.uci());
+                                *notice_2.lock().unwrap() = Some(Notice::new(&text));
+                            },
+                            (None, _) => (),
+                        }
+                    },
+                    Some(LilaMessage::End(winner)) => {
+                        pov.end(winner);
+                    },
+                    Some(LilaMessage::EndData(end)) => {
+                        pov.end_data(end);
+                    },
+                    Some(LilaMessage::DrawOffer(by)) => {
+                        pov.draw_offer(by);
+                    },
+                    Some(LilaMessage::TakebackOffers(offers)) => {
+                        pov.takeback_offers(&offers);
+                    },
+                    Some(LilaMessage::RematchOffer(by)) => {
+                        pov.rematch_offer(by);
+                    },
+                    Some(LilaMessage::RematchTaken(id)) => {
+                        pov.game.rematch = Some(id);
+                        *notice_2.lock().unwrap() = Some(Notice::new("Rematch started, Ctrl-O opens it"));
+                    },
+                    Some(LilaMessage::Gone(gone)) => {
+                        pov.opponent.isGone = gone;
+                    },
+                    Some(LilaMessage::GoneIn(seconds)) => {
+                        let text = format!("Opponent left, victory can be claimed in {}s", seconds);
+                        *notice_2.lock().unwrap() = Some(Notice::new(&text));
+                    },
+                    Some(LilaMessage::Clock(c)) => {
+                        pov.update_clock(c, latency_2.lock().unwrap().average());
+                    },
+                    Some(LilaMessage::Crowd(c)) => {
+                        pov.crowd = Some(c);
+                    },
+                    _ => ()
+                };
+            }
+            log::debug!("Socket task done");
         });
 
-        ConnectedPov {
+        Ok(ConnectedPov {
             pov: pov_1,
             latency: latency_1,
-            send_tx: send_tx,
-        }
+            status: status_1,
+            notice: notice_1,
+            ackable: ackable_1,
+            rejected: rejected_1,
+            premoves: premoves_1,
+            send_tx,
+        })
     }
 
-    pub fn send_move(&mut self, from: String, to: String) {
-        let move_packet = MovePacket {
-            t: "move".into(),
-            l: None, // TODO
-            d: Dest {
-                from: from,
-                to: to,
-                promotion: None,
+    /// Refetches the game and replaces the pov, for when socket events
+    /// may have been missed. Returns the version of the new pov.
+    async fn resync(session: &lila::Session, pov: &Arc<Mutex<Pov>>, version: &AtomicU64) -> Option<u64> {
+        let path = pov.lock().unwrap().url.round.trim_start_matches('/').to_owned();
+        log::debug!("Resyncing {}", path);
+        let fresh = session.get_async(&path).await
+            .and_then(|body| Ok(serde_json::from_str::<Pov>(&body)?));
+        match fresh {
+            Ok(fresh) => {
+                let synced = fresh.version();
+                *pov.lock().unwrap() = fresh;
+                version.fetch_max(synced, Ordering::SeqCst);
+                Some(synced)
             },
+            Err(e) => {
+                log::error!("Could not resync {}: {}", path, e);
+                None
+            },
+        }
+    }
+
+    /// Promotion is the piece letter, like q for queen
+    pub fn send_move(&mut self, from: String, to: String, promotion: Option<char>) {
+        let premove = Premove { from, to, promotion, after: None };
+        send_ackable(&self.ackable, &self.latency, &self.send_tx, "move", premove.uci(), premove.dest());
+    }
+
+    /// Drops a piece from the pocket in crazyhouse, the uci is like N@f3
+    pub fn send_drop(&mut self, role: Role, pos: String) {
+        let uci = format!("{}@{}", role.char().to_ascii_uppercase(), pos);
+        send_ackable(&self.ackable, &self.latency, &self.send_tx, "drop", uci, DropDest { role: role.name().to_owned(), pos });
+    }
+
+    /// Replaces the premove waiting for the same opponent move, it is sent
+    /// after that move if legal then. Conditional premoves only answer the
+    /// opponent's next move, whichever it is the others are dropped.
+    pub fn set_premove(&self, premove: Premove) {
+        let mut premoves = self.premoves.lock().unwrap();
+        premoves.retain(|p| p.after != premove.after);
+        premoves.push(premove);
+    }
+
+    pub fn cancel_premove(&self) {
+        self.premoves.lock().unwrap().clear();
+    }
+
+    pub fn premoves(&self) -> Vec<Premove> {
+        self.premoves.lock().unwrap().clone()
+    }
+
+    pub fn send_action(&self, action: Action) {
+        log::info!("Sending action: {:?}", action);
+        self.send(action.to_message());
+    }
+
+    /// Resends moves that aren't acknowledged yet,
+    /// and gives up on them after a while
+    pub fn tick(&mut self) {
+        let now = Instant::now();
+        let (resend, expired) = {
+            let mut ackable = self.ackable.lock().unwrap();
+            if ackable.is_empty() {
+                return;
+            }
+            (ackable.resend(now), ackable.expired(now))
         };
-        let message = serde_json::to_string(&move_packet).unwrap();
-        self.send_tx.send(message).unwrap();
+        for message in resend {
+            log::debug!("Resending: {}", message);
+            self.send(message);
+        }
+        if let Some(expired) = expired.into_iter().last() {
+            log::warn!("Move not acknowledged: {}", expired.subject);
+            *self.rejected.lock().unwrap() = Some(Rejected {
+                uci: expired.subject,
+                reason: "not acknowledged",
+            });
+        }
+    }
+
+    /// The last move lila didn't accept, if any since last time
+    pub fn take_rejected(&self) -> Option<Rejected> {
+        self.rejected.lock().unwrap().take()
+    }
+
+    fn send(&self, message: String) {
+        if self.send_tx.send(message).is_err() {
+            log::error!("Socket is gone, could not send");
+        }
+    }
+}
+
+/// Sends a move or drop that lila should acknowledge
+fn send_ackable<D: serde::Serialize>(
+    ackable: &Mutex<Ackable>,
+    latency: &Mutex<LatencyRecorder>,
+    send_tx: &mpsc::UnboundedSender<String>,
+    t: &str,
+    uci: String,
+    dest: D,
+) {
+    let mut ackable = ackable.lock().unwrap();
+    let id = ackable.next_id();
+    let move_packet = MovePacket {
+        t: t.into(),
+        d: MoveData {
+            dest,
+            l: Some(latency.lock().unwrap().average()),
+            a: id,
+        },
+    };
+    let message = serde_json::to_string(&move_packet).unwrap();
+    ackable.register(id, uci, message.clone());
+    if send_tx.send(message).is_err() {
+        log::error!("Socket is gone, could not send");
     }
 }
 
 #[derive(Serialize, Debug)]
-pub struct MovePacket {
+pub struct MovePacket<D> {
     t: String,
-    d: Dest,
+    d: MoveData<D>,
+}
+
+/// Lag and ack id are sent along with the move
+#[derive(Serialize, Debug)]
+pub struct MoveData<D> {
+    #[serde(flatten)]
+    dest: D,
     l: Option<i64>,
+    a: u64,
 }
 
 #[derive(Serialize, Debug)]
@@ -115,3 +393,41 @@ pub struct Dest {
     pub promotion: Option<String>,
 }
 
+#[derive(Serialize, Debug)]
+pub struct DropDest {
+    pub role: String,
+    pub pos: String,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn drop_packet() {
+        let packet = MovePacket {
+            t: "drop".into(),
+            d: MoveData { dest: DropDest { role: "knight".into(), pos: "f3".into() }, l: Some(20), a: 3 },
+        };
+        assert_eq!(serde_json::to_string(&packet).unwrap(), r#"{"t":"drop","d":{"role":"knight","pos":"f3","l":20,"a":3}}"#);
+    }
+
+    #[test]
+    fn premove() {
+        let premove = Premove { from: "e7".into(), to: "e8".into(), promotion: Some('n'), after: None };
+        assert_eq!(premove.uci(), "e7e8n");
+        let dest = premove.dest();
+        assert_eq!((dest.to.as_str(), dest.promotion.as_deref()), ("e8", Some("knight")));
+    }
+
+    #[test]
+    fn conditional_premoves() {
+        let premove = |uci: &str, after: Option<&str>| Premove {
+            from: uci[0..2].into(), to: uci[2..4].into(), promotion: None, after: after.map(|a| a.into()),
+        };
+        let premoves = vec!(premove("g1f3", None), premove("e4d5", Some("d7d5")), premove("d2d4", Some("e7e5")));
+        assert_eq!(Premove::reply(premoves.clone(), "d7d5"), Some(premove("e4d5", Some("d7d5"))));
+        assert_eq!(Premove::reply(premoves.clone(), "c7c5"), Some(premove("g1f3", None)));
+        assert_eq!(Premove::reply(premoves[1..].to_vec(), "c7c5"), None);
+    }
+}
@@ -1,15 +1,102 @@
+//! The messages lila sends over the socket, typed in full even where
+//! nothing reads a field yet, so the fixtures pin down the whole protocol.
+#![allow(dead_code)]
+
+use serde::Deserialize;
+use std::collections::HashMap;
 use serde_derive::Deserialize;
 
 use serde_json;
+use serde_json::json;
 
 use super::Clock;
+use super::Color;
 use super::Crowd;
+use super::Status;
 
+/// A message from the round or site socket, tagged by `t` with its data in `d`.
+/// Anything not known here is `Unknown`.
+#[derive(Deserialize, Debug)]
+#[serde(tag = "t", content = "d")]
 pub enum LilaMessage {
+    #[serde(rename = "n")]
     Pong(Pong),
+    #[serde(rename = "move")]
     Move(Move),
+    /// A crazyhouse piece drop, lila sends it in the shape of a move
+    #[serde(rename = "drop")]
+    Drop(Move),
+    #[serde(rename = "clock")]
     Clock(Clock),
+    /// Correspondence clock, in seconds
+    #[serde(rename = "cclock")]
+    CorrespondenceClock(Clock),
+    #[serde(rename = "crowd")]
     Crowd(Crowd),
+    /// Acknowledges a sent message by its id
+    #[serde(rename = "ack")]
+    Ack(Option<u64>),
+    /// The game must be reloaded, for example after rejecting a move
+    #[serde(rename = "resync", alias = "reload")]
+    Resync,
+    /// The game is over, with the winner unless it's a draw
+    #[serde(rename = "end")]
+    End(Option<Color>),
+    #[serde(rename = "endData")]
+    EndData(EndData),
+    /// Whether the opponent left the game
+    #[serde(rename = "gone")]
+    Gone(bool),
+    /// Seconds until victory can be claimed
+    #[serde(rename = "goneIn")]
+    GoneIn(u64),
+    #[serde(rename = "drawOffer")]
+    DrawOffer(Option<Color>),
+    #[serde(rename = "takebackOffers")]
+    TakebackOffers(Offers),
+    #[serde(rename = "rematchOffer")]
+    RematchOffer(Option<Color>),
+    /// Id of the new game
+    #[serde(rename = "rematchTaken")]
+    RematchTaken(String),
+    #[serde(rename = "redirect")]
+    Redirect(Redirect),
+    #[serde(rename = "berserk")]
+    Berserk(Color),
+    #[serde(rename = "tvSelect")]
+    TvSelect(TvSelect),
+    /// A chat line
+    #[serde(rename = "message")]
+    Message(ChatLine),
+    /// Server side lag in milliseconds
+    #[serde(rename = "mlat")]
+    Mlat(i64),
+    /// Position of a game shown as a mini board
+    #[serde(rename = "fen")]
+    Fen(Fen),
+    #[serde(rename = "following_onlines")]
+    FollowingOnlines(Vec<String>),
+    #[serde(rename = "following_enters")]
+    FollowingEnters(String),
+    #[serde(rename = "following_leaves")]
+    FollowingLeaves(String),
+    #[serde(rename = "following_playing")]
+    FollowingPlaying(String),
+    #[serde(rename = "following_stopped_playing")]
+    FollowingStoppedPlaying(String),
+    #[serde(rename = "challenges")]
+    Challenges(serde_json::Value),
+    #[serde(rename = "notifications")]
+    Notifications(serde_json::Value),
+    #[serde(rename = "analysisProgress")]
+    AnalysisProgress(serde_json::Value),
+    /// Id of a simul game where it's the player's turn
+    #[serde(rename = "simulPlayerMove")]
+    SimulPlayerMove(String),
+    #[serde(rename = "tournamentStanding")]
+    TournamentStanding(serde_json::Value),
+    #[serde(other)]
+    Unknown,
 }
 
 #[derive(Deserialize, Debug)]
@@ -17,40 +104,343 @@ pub struct Pong {
     pub latency: i64,
 }
 
-#[allow(dead_code)]
-#[derive(Deserialize)]
+#[allow(non_snake_case)]
+#[derive(Deserialize, Debug)]
 pub struct Move {
     pub clock: Option<Clock>,
-    // "dests": Object({"a6": String("a5"), "c7": String("b8d8b6a5d6e5f4g3h2"), "d5": String("c6e6"), "f5": String("f4")}),
-    //dests: String,
+    /// Legal moves of the side to move next
+    pub dests: Option<Dests>,
     pub fen: String,
     pub san: String, // Bc7
     pub uci: String, // e5c7
     pub ply: u64,
+    /// Only set for drops
+    pub role: Option<String>,
+    #[serde(default)]
+    pub check: bool,
+    #[serde(default)]
+    pub threefold: bool,
+    #[serde(default)]
+    pub wDraw: bool,
+    #[serde(default)]
+    pub bDraw: bool,
+    pub promotion: Option<Promotion>,
+    pub enpassant: Option<Enpassant>,
+    pub castle: Option<Castle>,
+    /// Set when the move ended the game
+    pub status: Option<Status>,
+    pub winner: Option<Color>,
+}
+
+#[allow(non_snake_case)]
+#[derive(Deserialize, Debug)]
+pub struct Promotion {
+    pub key: String,
+    pub pieceClass: String,
+}
+
+/// The square of the captured pawn
+#[derive(Deserialize, Debug)]
+pub struct Enpassant {
+    pub key: String,
+    pub color: Color,
+}
+
+/// From and to squares of both pieces
+#[derive(Deserialize, Debug)]
+pub struct Castle {
+    pub king: (String, String),
+    pub rook: (String, String),
+    pub color: Color,
+}
+
+#[allow(non_snake_case)]
+#[derive(Deserialize, Debug)]
+pub struct EndData {
+    pub winner: Option<Color>,
+    pub status: Status,
+    pub ratingDiff: Option<RatingDiff>,
+    pub clock: Option<EndClock>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RatingDiff {
+    pub white: i64,
+    pub black: i64,
+}
+
+/// Remaining time in centiseconds
+#[derive(Deserialize, Debug)]
+pub struct EndClock {
+    pub wc: i64,
+    pub bc: i64,
+}
+
+/// Legal moves, with the destinations of each square concatenated,
+/// like {"e2": "e3e4", "g1": "f3h3"}. Lila sends them either that way
+/// or as a string of square and destinations, like "e2e3e4 g1f3h3".
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Dests(HashMap<String, String>);
+
+impl<'de> Deserialize<'de> for Dests {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Dests, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Text(String),
+            Map(HashMap<String, String>),
+        }
+        Ok(match Raw::deserialize(deserializer)? {
+            Raw::Text(text) => Dests(text.split_whitespace()
+                .filter_map(|s| Some((s.get(0..2)?.to_owned(), s.get(2..)?.to_owned())))
+                .collect()),
+            Raw::Map(map) => Dests(map),
+        })
+    }
 }
 
+impl Dests {
+    /// Squares with a piece that can move
+    pub fn origins(&self) -> impl Iterator<Item = &str> {
+        self.0.keys().map(|s| s.as_str())
+    }
+
+    /// Where the piece on the square can go
+    pub fn targets(&self, square: &str) -> Vec<String> {
+        let dests = self.0.get(square).map_or("", |d| d.as_str());
+        (0..dests.len() / 2).filter_map(|i| dests.get(i * 2..i * 2 + 2)).map(|d| d.to_owned()).collect()
+    }
+
+    pub fn contains(&self, from: &str, to: &str) -> bool {
+        self.targets(from).iter().any(|d| d == to)
+    }
+}
+
+/// Which sides are offering, missing means not
+#[derive(Deserialize, Debug, Default)]
+pub struct Offers {
+    #[serde(default)]
+    pub white: bool,
+    #[serde(default)]
+    pub black: bool,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Redirect {
+    pub id: String,
+    pub url: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TvSelect {
+    pub channel: String,
+    pub id: String,
+    pub color: Color,
+}
+
+/// `u` is missing for system messages
+#[derive(Deserialize, Debug)]
+pub struct ChatLine {
+    pub u: Option<String>,
+    pub t: String,
+}
+
+/// Clocks are in seconds
+#[derive(Deserialize, Debug)]
+pub struct Fen {
+    pub id: String,
+    pub fen: String,
+    pub lm: Option<String>,
+    pub wc: Option<i64>,
+    pub bc: Option<i64>,
+}
 
 impl LilaMessage {
+    /// Like `deserialize`, but also gives `Unknown` for unknown messages with data
+    pub fn parse(obj: &serde_json::Value) -> Result<LilaMessage, serde_json::Error> {
+        LilaMessage::deserialize(obj).or_else(|e| {
+            let tag_only = json!({ "t": obj.get("t") });
+            match LilaMessage::deserialize(&tag_only) {
+                Ok(LilaMessage::Unknown) => Ok(LilaMessage::Unknown),
+                _ => Err(e),
+            }
+        })
+    }
+
     pub fn decode(obj: &serde_json::Value) -> Option<LilaMessage> {
-        let data = obj.get("d").and_then(|d| serde_json::to_string(d).ok());
-        match (obj.get("t").and_then(|t| t.as_str()), data) {
-            // TODO: gone, crowd, end, tvSelect, challenges, drop,
-            // following_enters, following_leaves, following_onlines,
-            // following_playing, following_stopped_plaing,
-            // message, analysisProgress, reload, and more
-            (Some("n"), Some(data)) => serde_json::from_str(&data).map(|d| LilaMessage::Pong(d)).ok(),
-            (Some("move"), Some(data)) => serde_json::from_str(&data).map(|d| LilaMessage::Move(d)).ok(),
-            (Some("clock"), Some(data)) => serde_json::from_str(&data).map(|d| LilaMessage::Clock(d)).ok(),
-            (Some("crowd"), Some(data)) => serde_json::from_str(&data).map(|d| LilaMessage::Crowd(d)).ok(),
-            (Some(ref t), ref d) => {
-                log::warn!("unhandled: {}, {:?}", t, d);
+        match LilaMessage::parse(obj) {
+            Ok(LilaMessage::Unknown) => {
+                log::warn!("unhandled: {}", obj);
                 None
             },
-            _ => {
-                log::warn!("unhandled: Missing type");
+            Ok(message) => Some(message),
+            Err(e) => {
+                log::warn!("could not decode: {}, {}", e, obj);
                 None
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn fixture(json: &str) -> LilaMessage {
+        let obj: serde_json::Value = serde_json::from_str(json).unwrap();
+        match LilaMessage::parse(&obj) {
+            Ok(message) => message,
+            Err(e) => panic!("{}: {}", e, json),
+        }
+    }
+
+    macro_rules! decodes {
+        ($name:ident, $file:expr, $pattern:pat => $check:expr) => {
+            #[test]
+            fn $name() {
+                match fixture(include_str!(concat!("../../fixtures/socket/", $file))) {
+                    $pattern => $check,
+                    other => panic!("decoded as {:?}", other),
+                }
             }
+        };
+    }
+
+    decodes!(move_plain, "move.json", LilaMessage::Move(m) => {
+        assert_eq!(m.uci, "e2e4");
+        assert_eq!(m.san, "e4");
+        assert_eq!(m.ply, 1);
+        assert_eq!(m.fen, "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR");
+        let clock = m.clock.unwrap();
+        assert_eq!((clock.white, clock.black), (180.0, 180.0));
+        assert!(!m.check);
+        assert!(m.promotion.is_none());
+        assert!(m.status.is_none());
+        let dests = m.dests.unwrap();
+        assert_eq!(dests.targets("g8"), vec!("f6", "h6"));
+        assert!(dests.contains("e7", "e5"));
+        assert!(!dests.contains("e7", "e4"));
+        assert!(dests.targets("e2").is_empty());
+    });
+    decodes!(move_dests_string, "move_dests_string.json", LilaMessage::Move(m) => {
+        let dests = m.dests.unwrap();
+        assert_eq!(dests.targets("g8"), vec!("f6", "h6"));
+        assert!(dests.contains("e7", "e5"));
+        assert!(dests.targets("e2").is_empty());
+    });
+    decodes!(move_promotion, "move_promotion.json", LilaMessage::Move(m) => {
+        assert!(m.check);
+        assert!(m.bDraw);
+        assert!(!m.wDraw);
+        let promotion = m.promotion.unwrap();
+        assert_eq!(promotion.key, "e8");
+        assert_eq!(promotion.pieceClass, "queen");
+    });
+    decodes!(move_castle, "move_castle.json", LilaMessage::Move(m) => {
+        let castle = m.castle.unwrap();
+        assert_eq!(castle.king, ("e1".into(), "g1".into()));
+        assert_eq!(castle.rook, ("h1".into(), "f1".into()));
+        assert_eq!(castle.color, Color::white);
+    });
+    decodes!(move_enpassant, "move_enpassant.json", LilaMessage::Move(m) => {
+        let enpassant = m.enpassant.unwrap();
+        assert_eq!((enpassant.key.as_str(), enpassant.color), ("d5", Color::white));
+        assert!(!m.threefold);
+        assert!(m.clock.is_none());
+    });
+    decodes!(move_end, "move_end.json", LilaMessage::Move(m) => {
+        assert_eq!(m.status.unwrap().name, "mate");
+        assert_eq!(m.winner, Some(Color::white));
+    });
+    decodes!(drop, "drop.json", LilaMessage::Drop(m) => {
+        assert_eq!(m.uci, "N@f3");
+        assert_eq!(m.role.as_deref(), Some("knight"));
+    });
+    decodes!(clock, "clock.json", LilaMessage::Clock(c) => {
+        assert_eq!((c.white, c.black), (58.42, 61.3));
+    });
+    decodes!(correspondence_clock, "cclock.json", LilaMessage::CorrespondenceClock(c) => {
+        assert_eq!(c.white, 259195.0);
+    });
+    decodes!(crowd, "crowd.json", LilaMessage::Crowd(c) => {
+        assert!(c.white);
+        assert!(!c.black);
+        assert_eq!(c.watchers.nb, 3);
+        assert_eq!(c.watchers.anons, Some(1));
+    });
+    decodes!(ack, "ack.json", LilaMessage::Ack(Some(3)) => ());
+    decodes!(ack_empty, "ack_empty.json", LilaMessage::Ack(None) => ());
+    decodes!(resync, "resync.json", LilaMessage::Resync => ());
+    decodes!(reload, "reload.json", LilaMessage::Resync => ());
+    decodes!(end, "end.json", LilaMessage::End(Some(Color::black)) => ());
+    decodes!(end_draw, "end_draw.json", LilaMessage::End(None) => ());
+    decodes!(end_data, "end_data.json", LilaMessage::EndData(e) => {
+        assert_eq!(e.winner, Some(Color::black));
+        assert_eq!(e.status.id, 35);
+        let diff = e.ratingDiff.unwrap();
+        assert_eq!((diff.white, diff.black), (-8, 7));
+        assert_eq!(e.clock.unwrap().bc, 4210);
+    });
+    decodes!(gone, "gone.json", LilaMessage::Gone(true) => ());
+    decodes!(gone_in, "gone_in.json", LilaMessage::GoneIn(13) => ());
+    decodes!(draw_offer, "draw_offer.json", LilaMessage::DrawOffer(Some(Color::white)) => ());
+    decodes!(takeback_offers, "takeback_offers.json", LilaMessage::TakebackOffers(o) => {
+        assert!(!o.white);
+        assert!(o.black);
+    });
+    decodes!(rematch_offer, "rematch_offer.json", LilaMessage::RematchOffer(Some(Color::white)) => ());
+    decodes!(rematch_taken, "rematch_taken.json", LilaMessage::RematchTaken(id) => {
+        assert_eq!(id, "Xk3sW9pq");
+    });
+    decodes!(redirect, "redirect.json", LilaMessage::Redirect(r) => {
+        assert_eq!(r.id, "Xk3sW9pqa1B2");
+        assert_eq!(r.url, "/Xk3sW9pqa1B2");
+    });
+    decodes!(berserk, "berserk.json", LilaMessage::Berserk(Color::black) => ());
+    decodes!(tv_select, "tv_select.json", LilaMessage::TvSelect(tv) => {
+        assert_eq!(tv.channel, "blitz");
+        assert_eq!(tv.id, "q7ZvsdUF");
+        assert_eq!(tv.color, Color::white);
+    });
+    decodes!(message, "message.json", LilaMessage::Message(line) => {
+        assert_eq!(line.u.as_deref(), Some("flugsio"));
+        assert_eq!(line.t, "good luck");
+    });
+    decodes!(mlat, "mlat.json", LilaMessage::Mlat(4) => ());
+    decodes!(fen, "fen.json", LilaMessage::Fen(f) => {
+        assert_eq!(f.id, "q7ZvsdUF");
+        assert_eq!(f.fen, "r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R");
+        assert_eq!(f.lm.as_deref(), Some("b8c6"));
+        assert_eq!((f.wc, f.bc), (Some(176), Some(179)));
+    });
+    decodes!(following_onlines, "following_onlines.json", LilaMessage::FollowingOnlines(names) => {
+        assert_eq!(names, vec!("thibault", "flugsio"));
+    });
+    decodes!(following_enters, "following_enters.json", LilaMessage::FollowingEnters(_) => ());
+    decodes!(following_leaves, "following_leaves.json", LilaMessage::FollowingLeaves(_) => ());
+    decodes!(following_playing, "following_playing.json", LilaMessage::FollowingPlaying(_) => ());
+    decodes!(following_stopped_playing, "following_stopped_playing.json",
+             LilaMessage::FollowingStoppedPlaying(_) => ());
+    decodes!(challenges, "challenges.json", LilaMessage::Challenges(_) => ());
+    decodes!(notifications, "notifications.json", LilaMessage::Notifications(_) => ());
+    decodes!(analysis_progress, "analysis_progress.json", LilaMessage::AnalysisProgress(_) => ());
+    decodes!(simul_player_move, "simul_player_move.json", LilaMessage::SimulPlayerMove(_) => ());
+    decodes!(tournament_standing, "tournament_standing.json", LilaMessage::TournamentStanding(_) => ());
+    decodes!(unknown, "unknown.json", LilaMessage::Unknown => ());
+
+    #[test]
+    fn pong() {
+        let obj = serde_json::json!({"t": "n", "d": {"latency": 31}});
+        match LilaMessage::decode(&obj) {
+            Some(LilaMessage::Pong(p)) => assert_eq!(p.latency, 31),
+            _ => panic!("not a pong"),
         }
     }
 
+    #[test]
+    fn decode_skips_unknown_and_invalid() {
+        assert!(LilaMessage::decode(&serde_json::json!({"t": "deployPost"})).is_none());
+        assert!(LilaMessage::decode(&serde_json::json!({"t": "move", "d": {}})).is_none());
+        assert!(LilaMessage::decode(&serde_json::json!({"d": 1})).is_none());
+    }
 }
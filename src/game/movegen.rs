@@ -0,0 +1,409 @@
+//! Legal moves by the rules of standard chess, with castling as in
+//! Chess960 so both work, and detecting check, mate and stalemate
+
+use std::fmt;
+
+use super::Color;
+use super::board::{Board, Piece, Role, Square};
+
+/// A move on the board, castling is the king moving to the rook
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Move {
+    pub from: Square,
+    pub to: Square,
+    pub promotion: Option<Role>,
+    pub castle: bool,
+}
+
+/// What the side to move is facing
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Situation {
+    Normal,
+    Check,
+    Checkmate,
+    Stalemate,
+}
+
+const KNIGHT: [(i8, i8); 8] = [(1, 2), (2, 1), (2, -1), (1, -2), (-1, -2), (-2, -1), (-2, 1), (-1, 2)];
+const KING: [(i8, i8); 8] = [(0, 1), (1, 1), (1, 0), (1, -1), (0, -1), (-1, -1), (-1, 0), (-1, 1)];
+const ROOK: [(i8, i8); 4] = [(0, 1), (1, 0), (0, -1), (-1, 0)];
+const BISHOP: [(i8, i8); 4] = [(1, 1), (1, -1), (-1, -1), (-1, 1)];
+const PROMOTIONS: [Role; 4] = [Role::Queen, Role::Rook, Role::Bishop, Role::Knight];
+
+impl Move {
+    fn new(from: Square, to: Square) -> Move {
+        Move { from, to, promotion: None, castle: false }
+    }
+
+    /// Where the king ends up when castling
+    pub fn king_to(&self) -> Square {
+        if !self.castle {
+            return self.to;
+        }
+        let file = if self.to.file > self.from.file { 6 } else { 2 };
+        Square::new(file, self.from.rank)
+    }
+
+    /// Whether this is the move between the squares, castling may also
+    /// be given as the king moving two squares like e1g1
+    pub fn matches(&self, from: Square, to: Square) -> bool {
+        self.from == from && (self.to == to || self.king_to() == to)
+    }
+}
+
+impl fmt::Display for Move {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}", self.from, self.to)?;
+        match self.promotion {
+            Some(role) => write!(f, "{}", role.char()),
+            None => Ok(()),
+        }
+    }
+}
+
+fn back_rank(color: Color) -> u8 {
+    if color == Color::white { 0 } else { 7 }
+}
+
+fn forward(color: Color) -> i8 {
+    if color == Color::white { 1 } else { -1 }
+}
+
+pub fn legal_moves(board: &Board) -> Vec<Move> {
+    let mut moves = pseudo_legal_moves(board);
+    moves.retain(|m| m.castle || !in_check_after(board, m));
+    moves.extend(castling_moves(board));
+    moves
+}
+
+/// Legal moves of the piece on the square, for showing where it can go
+pub fn moves_from(board: &Board, from: Square) -> Vec<Move> {
+    legal_moves(board).into_iter().filter(|m| m.from == from).collect()
+}
+
+/// The legal move between the squares, if there is one
+pub fn find(board: &Board, from: Square, to: Square) -> Option<Move> {
+    legal_moves(board).into_iter().find(|m| m.matches(from, to))
+}
+
+pub fn in_check(board: &Board) -> bool {
+    board.king(board.turn).is_some_and(|king| attacked(board, king, !board.turn))
+}
+
+pub fn situation(board: &Board) -> Situation {
+    match (in_check(board), legal_moves(board).is_empty()) {
+        (true, true) => Situation::Checkmate,
+        (true, false) => Situation::Check,
+        (false, true) => Situation::Stalemate,
+        (false, false) => Situation::Normal,
+    }
+}
+
+fn pseudo_legal_moves(board: &Board) -> Vec<Move> {
+    let mut moves = vec!();
+    for from in Square::all() {
+        let piece = match board.get(from) {
+            Some(piece) if piece.color == board.turn => piece,
+            _ => continue,
+        };
+        let mut add = |to: Square| {
+            match board.get(to) {
+                Some(target) if target.color == piece.color => false,
+                Some(_) => {
+                    moves.push(Move::new(from, to));
+                    false
+                },
+                None => {
+                    moves.push(Move::new(from, to));
+                    true
+                },
+            }
+        };
+        match piece.role {
+            Role::Pawn => pawn_moves(board, from, piece.color, &mut moves),
+            Role::Knight | Role::King => {
+                let steps = if piece.role == Role::Knight { &KNIGHT } else { &KING };
+                for &(df, dr) in steps {
+                    if let Some(to) = from.offset(df, dr) {
+                        add(to);
+                    }
+                }
+            },
+            Role::Bishop | Role::Rook | Role::Queen => {
+                let directions = match piece.role {
+                    Role::Bishop => &BISHOP[..],
+                    Role::Rook => &ROOK[..],
+                    _ => &KING[..],
+                };
+                for &(df, dr) in directions {
+                    let mut square = from;
+                    while let Some(to) = square.offset(df, dr) {
+                        if !add(to) {
+                            break;
+                        }
+                        square = to;
+                    }
+                }
+            },
+        }
+    }
+    moves
+}
+
+fn pawn_moves(board: &Board, from: Square, color: Color, moves: &mut Vec<Move>) {
+    let dir = forward(color);
+    let last = back_rank(!color);
+    let mut push = |to: Square| {
+        if to.rank == last {
+            for &role in &PROMOTIONS {
+                moves.push(Move { promotion: Some(role), ..Move::new(from, to) });
+            }
+        } else {
+            moves.push(Move::new(from, to));
+        }
+    };
+    if let Some(to) = from.offset(0, dir).filter(|&s| board.get(s).is_none()) {
+        push(to);
+        let start = if color == Color::white { 1 } else { 6 };
+        if from.rank == start {
+            if let Some(to) = to.offset(0, dir).filter(|&s| board.get(s).is_none()) {
+                push(to);
+            }
+        }
+    }
+    for df in [-1, 1] {
+        if let Some(to) = from.offset(df, dir) {
+            let capture = board.get(to).is_some_and(|p| p.color != color);
+            if capture || board.en_passant == Some(to) {
+                push(to);
+            }
+        }
+    }
+}
+
+/// The rook square each castling right refers to
+fn castling_rooks(board: &Board, color: Color) -> Vec<(char, Square)> {
+    let back = back_rank(color);
+    let king = match board.king(color) {
+        Some(king) if king.rank == back => king,
+        _ => return vec!(),
+    };
+    let rook = Piece::new(color, Role::Rook);
+    let rooks: Vec<u8> = (0..8).filter(|&f| board.get(Square::new(f, back)) == Some(rook)).collect();
+    board.castling.chars()
+        .filter(|c| c.is_ascii_uppercase() == (color == Color::white))
+        .filter_map(|c| {
+            let file = match c.to_ascii_lowercase() {
+                // the outermost rook, as in X-FEN
+                'k' => rooks.iter().cloned().filter(|&f| f > king.file).max(),
+                'q' => rooks.iter().cloned().filter(|&f| f < king.file).min(),
+                // the rook file, as in Shredder-FEN
+                f => Some(f as u8 - b'a').filter(|f| rooks.contains(f)),
+            };
+            file.map(|f| (c, Square::new(f, back)))
+        })
+        .collect()
+}
+
+fn castling_moves(board: &Board) -> Vec<Move> {
+    let color = board.turn;
+    let king = match board.king(color) {
+        Some(king) if king.rank == back_rank(color) && !in_check(board) => king,
+        _ => return vec!(),
+    };
+    let mut moves = vec!();
+    for (_, rook) in castling_rooks(board, color) {
+        let m = Move { castle: true, ..Move::new(king, rook) };
+        let king_to = m.king_to();
+        let rook_to = Square::new(if rook.file > king.file { 5 } else { 3 }, rook.rank);
+        let span = |a: Square, b: Square| {
+            (a.file.min(b.file)..=a.file.max(b.file)).map(move |f| Square::new(f, a.rank))
+        };
+        let blocked = span(king, king_to).chain(span(rook, rook_to))
+            .any(|s| s != king && s != rook && board.get(s).is_some());
+        if blocked {
+            continue;
+        }
+        let mut without = board.clone();
+        without.set(king, None);
+        without.set(rook, None);
+        if span(king, king_to).any(|s| attacked(&without, s, !color)) {
+            continue;
+        }
+        if !in_check_after(board, &m) {
+            moves.push(m);
+        }
+    }
+    moves
+}
+
+fn in_check_after(board: &Board, m: &Move) -> bool {
+    let after = play(board, m);
+    after.king(board.turn).is_some_and(|king| attacked(&after, king, !board.turn))
+}
+
+/// Whether a piece of the color attacks the square
+pub fn attacked(board: &Board, square: Square, by: Color) -> bool {
+    let is = |s: Option<Square>, roles: &[Role]| {
+        s.and_then(|s| board.get(s)).is_some_and(|p| p.color == by && roles.contains(&p.role))
+    };
+    let pawn_dir = -forward(by);
+    if is(square.offset(-1, pawn_dir), &[Role::Pawn]) || is(square.offset(1, pawn_dir), &[Role::Pawn]) {
+        return true;
+    }
+    if KNIGHT.iter().any(|&(df, dr)| is(square.offset(df, dr), &[Role::Knight])) {
+        return true;
+    }
+    if KING.iter().any(|&(df, dr)| is(square.offset(df, dr), &[Role::King])) {
+        return true;
+    }
+    let slides = |directions: &[(i8, i8)], roles: &[Role]| {
+        directions.iter().any(|&(df, dr)| {
+            let mut s = square;
+            while let Some(next) = s.offset(df, dr) {
+                if board.get(next).is_some() {
+                    return is(Some(next), roles);
+                }
+                s = next;
+            }
+            false
+        })
+    };
+    slides(&ROOK, &[Role::Rook, Role::Queen]) || slides(&BISHOP, &[Role::Bishop, Role::Queen])
+}
+
+/// The position after a move, which should be at least pseudo legal
+pub fn play(board: &Board, m: &Move) -> Board {
+    let mut after = board.clone();
+    let color = board.turn;
+    let piece = match board.get(m.from) {
+        Some(piece) => piece,
+        None => return after,
+    };
+    let capture = !m.castle && board.get(m.to).is_some();
+    after.en_passant = None;
+    if m.castle {
+        let rook_to = Square::new(if m.to.file > m.from.file { 5 } else { 3 }, m.from.rank);
+        let rook = board.get(m.to);
+        after.set(m.from, None);
+        after.set(m.to, None);
+        after.set(m.king_to(), Some(piece));
+        after.set(rook_to, rook);
+    } else {
+        after.set(m.from, None);
+        if piece.role == Role::Pawn && Some(m.to) == board.en_passant && m.from.file != m.to.file {
+            after.set(Square::new(m.to.file, m.from.rank), None);
+        }
+        if piece.role == Role::Pawn && (m.to.rank as i8 - m.from.rank as i8).abs() == 2 {
+            after.en_passant = Some(Square::new(m.from.file, (m.from.rank + m.to.rank) / 2));
+        }
+        let placed = match m.promotion {
+            Some(role) => Piece { role, promoted: true, ..piece },
+            None => piece,
+        };
+        after.set(m.to, Some(placed));
+    }
+    let rooks: Vec<(char, Square)> = castling_rooks(board, Color::white).into_iter()
+        .chain(castling_rooks(board, Color::black))
+        .collect();
+    after.castling = board.castling.chars()
+        .filter(|&c| {
+            let own = c.is_ascii_uppercase() == (color == Color::white);
+            let king_moved = own && piece.role == Role::King;
+            let rook_gone = rooks.iter().any(|&(r, s)| r == c && (s == m.from || s == m.to));
+            rooks.iter().any(|&(r, _)| r == c) && !king_moved && !rook_gone
+        })
+        .collect();
+    after.halfmoves = if capture || piece.role == Role::Pawn { 0 } else { board.halfmoves + 1 };
+    if color == Color::black {
+        after.fullmoves += 1;
+    }
+    after.turn = !color;
+    after
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const START: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+    fn perft(board: &Board, depth: u32) -> u64 {
+        let moves = legal_moves(board);
+        if depth == 1 {
+            return moves.len() as u64;
+        }
+        moves.iter().map(|m| perft(&play(board, m), depth - 1)).sum()
+    }
+
+    fn assert_perft(fen: &str, counts: &[u64]) {
+        let board = Board::from_fen(fen).unwrap();
+        for (depth, &count) in counts.iter().enumerate() {
+            assert_eq!(perft(&board, depth as u32 + 1), count, "depth {} of {}", depth + 1, fen);
+        }
+    }
+
+    #[test]
+    fn perft_start() {
+        assert_perft(START, &[20, 400, 8902]);
+    }
+
+    #[test]
+    fn perft_kiwipete() {
+        assert_perft("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1", &[48, 2039, 97862]);
+    }
+
+    #[test]
+    fn perft_endgame() {
+        assert_perft("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1", &[14, 191, 2812, 43238]);
+    }
+
+    #[test]
+    fn perft_promotions() {
+        assert_perft("r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1", &[6, 264, 9467]);
+        assert_perft("rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8", &[44, 1486, 62379]);
+    }
+
+    #[test]
+    fn perft_chess960() {
+        assert_perft("bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9", &[21, 528, 12189]);
+        assert_perft("2nnrbkr/p1qppppp/8/1ppb4/6PP/3PP3/PPP2P2/BQNNRBKR w HEhe - 1 9", &[21, 807, 18002]);
+    }
+
+    #[test]
+    fn castling_both_ways() {
+        let board = Board::from_fen("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1").unwrap();
+        let e1 = Square::parse("e1").unwrap();
+        let short = find(&board, e1, Square::parse("g1").unwrap()).unwrap();
+        assert!(short.castle);
+        assert_eq!(short.to.to_string(), "h1");
+        assert_eq!(find(&board, e1, Square::parse("h1").unwrap()), Some(short));
+        let after = play(&board, &short);
+        assert_eq!(after.to_fen(), "r3k2r/8/8/8/8/8/8/R4RK1 b kq - 1 1");
+    }
+
+    #[test]
+    fn castling_rights_lost() {
+        let board = Board::from_fen("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1").unwrap();
+        let rook_move = Move::new(Square::parse("a1").unwrap(), Square::parse("a8").unwrap());
+        // taking the rook on a8 loses black its long castling too
+        assert_eq!(play(&board, &rook_move).castling, "Kk");
+    }
+
+    #[test]
+    fn situations() {
+        let board = |fen| Board::from_fen(fen).unwrap();
+        assert_eq!(situation(&board(START)), Situation::Normal);
+        let fools_mate = "rnb1kbnr/pppp1ppp/8/4p3/6Pq/5P2/PPPPP2P/RNBQKBNR w KQkq - 1 3";
+        assert_eq!(situation(&board(fools_mate)), Situation::Checkmate);
+        let check = "rnbqkbnr/ppp2ppp/3p4/1B2p3/4P3/8/PPPP1PPP/RNBQK1NR b KQkq - 1 3";
+        assert_eq!(situation(&board(check)), Situation::Check);
+        assert_eq!(situation(&board("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1")), Situation::Stalemate);
+    }
+
+    #[test]
+    fn uci() {
+        let m = Move { promotion: Some(Role::Knight), ..Move::new(Square::parse("e7").unwrap(), Square::parse("e8").unwrap()) };
+        assert_eq!(m.to_string(), "e7e8n");
+    }
+}
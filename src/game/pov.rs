@@ -1,10 +1,18 @@
 use serde_derive::Deserialize;
 
+use super::board::{Board, Role, Square};
 use super::Clock;
 use super::Crowd;
 use super::Color;
+use super::Dests;
+use super::EndData;
 use super::Game;
+use super::Offers;
+use super::Status;
+use super::movegen;
+use super::variant;
 
+#[allow(non_snake_case)]
 #[derive(Deserialize)]
 pub struct Pov {
     pub game: Game,
@@ -16,6 +24,11 @@ pub struct Pov {
     pub opponent: Player,
     pub tv: Option<Tv>,
     pub orientation: Option<Color>,
+    /// Legal moves, only sent when it is the player's turn
+    pub possibleMoves: Option<Dests>,
+    /// Squares cleared by the last capture in atomic
+    #[serde(skip)]
+    pub explosion: Vec<Square>,
 }
 
 #[derive(Deserialize)]
@@ -24,6 +37,7 @@ pub struct Tv {
     pub flip: bool,
 }
 
+#[allow(non_snake_case)]
 #[derive(Deserialize)]
 pub struct Player {
     pub color: Color,
@@ -31,6 +45,18 @@ pub struct Player {
     pub spectator: Option<bool>,
     pub user: Option<User>,
     pub rating: Option<i64>,
+    pub ratingDiff: Option<i64>,
+    #[serde(default)]
+    pub offeringDraw: bool,
+    #[serde(default)]
+    pub proposingTakeback: bool,
+    #[serde(default)]
+    pub offeringRematch: bool,
+    #[serde(default)]
+    pub isGone: bool,
+    /// Checks given, only sent in three-check
+    #[serde(default)]
+    pub checks: u32,
 }
 
 #[derive(Deserialize)]
@@ -59,14 +85,270 @@ impl Pov {
     }
 
     pub fn tick(&mut self) {
+        if self.game.status.finished() {
+            return;
+        }
         // FUTURE: `let` is only needed bc rust borrow checker is lazy
         let color = self.game.player;
         self.clock.as_mut().map(|c| c.tick(color));
     }
 
+    fn players_mut(&mut self) -> [&mut Player; 2] {
+        [&mut self.player, &mut self.opponent]
+    }
+
+    pub fn draw_offer(&mut self, by: Option<Color>) {
+        for player in self.players_mut() {
+            player.offeringDraw = Some(player.color) == by;
+        }
+    }
+
+    pub fn takeback_offers(&mut self, offers: &Offers) {
+        for player in self.players_mut() {
+            player.proposingTakeback = match player.color {
+                Color::white => offers.white,
+                Color::black => offers.black,
+            };
+        }
+    }
+
+    pub fn rematch_offer(&mut self, by: Option<Color>) {
+        for player in self.players_mut() {
+            player.offeringRematch = Some(player.color) == by;
+        }
+    }
+
+    /// Keeps what the variant needs from a move once it is applied,
+    /// the explosion in atomic and the checks given in three-check
+    pub fn moved(&mut self, before: Option<Board>, check: bool) {
+        let mover = !self.game.player;
+        if check && self.game.variant.key == "threeCheck" {
+            for player in self.players_mut() {
+                if player.color == mover {
+                    player.checks += 1;
+                }
+            }
+        }
+        let square = |range| self.game.lastMove.as_ref().and_then(|uci| uci.get(range)).and_then(Square::parse);
+        let (from, to) = (square(0..2), square(2..4));
+        self.explosion = match (self.game.variant.key.as_str(), before, self.board(), from, to) {
+            ("atomic", Some(before), Ok(after), Some(from), Some(to)) => variant::explosion(&before, &after, from, to),
+            _ => vec!(),
+        };
+    }
+
+    /// Offers waiting for an answer, and whether the opponent left
+    pub fn pending(&self) -> Vec<String> {
+        let mut pending = vec!();
+        for player in [&self.opponent, &self.player] {
+            let name = player.color.name();
+            if player.offeringDraw {
+                pending.push(format!("{} offers a draw", name));
+            }
+            if player.proposingTakeback {
+                pending.push(format!("{} proposes a takeback", name));
+            }
+            if player.offeringRematch {
+                pending.push(format!("{} wants a rematch", name));
+            }
+            if player.isGone {
+                pending.push(format!("{} left the game", name));
+            }
+        }
+        pending
+    }
+
+    /// The game is over, endData may follow with the details
+    pub fn end(&mut self, winner: Option<Color>) {
+        self.game.winner = winner;
+        if !self.game.status.finished() {
+            self.game.status = Status::unknown_finish();
+        }
+        if let Some(clock) = self.clock.as_mut() {
+            clock.running = false;
+        }
+    }
+
+    /// Takes the times from a move or clock event, keeping the time control.
+    /// Clocks run once both players moved, until the game is over.
+    pub fn update_clock(&mut self, update: Clock, latency_ms: i64) {
+        let running = self.game.turns >= 2 && !self.game.status.finished();
+        match self.clock.as_mut() {
+            Some(clock) => clock.update(update.white, update.black, running, latency_ms),
+            None => {
+                let mut clock = update;
+                clock.running = running;
+                self.clock = Some(clock);
+            },
+        }
+    }
+
+    pub fn end_data(&mut self, end: EndData) {
+        self.game.status = end.status;
+        self.game.winner = end.winner;
+        if let Some(diff) = end.ratingDiff {
+            for player in self.players_mut() {
+                player.ratingDiff = Some(match player.color {
+                    Color::white => diff.white,
+                    Color::black => diff.black,
+                });
+            }
+        }
+        if let (Some(clock), Some(end_clock)) = (self.clock.as_mut(), end.clock) {
+            clock.white = end_clock.wc as f64 / 100.0;
+            clock.black = end_clock.bc as f64 / 100.0;
+        }
+    }
+
+    /// Version of the last socket event included
+    pub fn version(&self) -> u64 {
+        self.player.version.unwrap_or(0) as u64
+    }
+
+    /// The current position. After moves lila only sends the piece
+    /// placement, so the rest is filled in from what else is known.
+    pub fn board(&self) -> Result<Board, String> {
+        let mut board = Board::from_fen(&self.game.fen)?;
+        if self.game.fen.split_whitespace().nth(1).is_none() {
+            board.turn = self.game.player;
+            board.fullmoves = self.game.turns as u32 / 2 + 1;
+            board.infer_castling();
+            board.en_passant = self.game.lastMove.as_ref().and_then(|uci| double_push(&board, uci));
+        }
+        Ok(board)
+    }
+
+    /// Whether moving from one square to the other is a pawn
+    /// reaching the last rank, so a piece must be picked
+    pub fn is_promotion(&self, from: &str, to: &str) -> bool {
+        let board = match self.board() {
+            Ok(board) => board,
+            Err(_) => return false,
+        };
+        match (Square::parse(from).and_then(|s| board.get(s)), Square::parse(to)) {
+            (Some(piece), Some(to)) if piece.role == Role::Pawn => {
+                to.rank == if piece.color == Color::white { 7 } else { 0 }
+            },
+            _ => false,
+        }
+    }
+
+    /// The legal moves lila sent, when they are this player's to make
+    pub fn dests(&self) -> Option<&Dests> {
+        self.possibleMoves.as_ref().filter(|_| self.movable())
+    }
+
+    /// Whether moves follow the rules of standard chess, so they
+    /// can be checked before sending them
+    pub fn standard_rules(&self) -> bool {
+        matches!(self.game.variant.key.as_str(), "standard" | "chess960" | "fromPosition" | "kingOfTheHill" | "threeCheck")
+    }
+
+    /// The position, when its moves can be checked here
+    pub fn checked_board(&self) -> Option<Board> {
+        Some(self).filter(|p| p.standard_rules()).and_then(|p| p.board().ok())
+    }
+
+    /// Checked against the moves lila sent, or else the rules of standard
+    /// chess when they apply, other variants are left to lila
+    pub fn legal(&self, from: &str, to: &str) -> bool {
+        if let Some(dests) = self.dests() {
+            return dests.contains(from, to);
+        }
+        let board = match self.checked_board() {
+            Some(board) => board,
+            None => return true,
+        };
+        match (Square::parse(from), Square::parse(to)) {
+            (Some(from), Some(to)) => movegen::find(&board, from, to).is_some(),
+            _ => false,
+        }
+    }
+
+    pub fn spectator(&self) -> bool {
+        self.player.spectator == Some(true)
+    }
+
+    /// Whether the user plays in this game and it is still going
+    pub fn playing(&self) -> bool {
+        !self.spectator() && !self.game.status.finished()
+    }
+
+    /// Whether it is the user's turn to move
     pub fn movable(&self) -> bool {
-        // TODO: implement
-        true
+        self.playing() && self.game.player == self.player.color
+    }
+}
+
+/// The square skipped by a pawn moving two steps, for en passant
+fn double_push(board: &Board, uci: &str) -> Option<Square> {
+    let from = Square::parse(uci.get(0..2)?)?;
+    let to = Square::parse(uci.get(2..4)?)?;
+    let pawn = board.get(to).filter(|p| p.role == Role::Pawn);
+    if pawn.is_some() && from.file == to.file && (from.rank as i8 - to.rank as i8).abs() == 2 {
+        Some(Square::new(from.file, (from.rank + to.rank) / 2))
+    } else {
+        None
     }
 }
 
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn pov(player: Color, turn: Color, spectator: bool, status: i64) -> Pov {
+        serde_json::from_value(round(player, turn, spectator, status)).unwrap()
+    }
+
+    fn round(player: Color, turn: Color, spectator: bool, status: i64) -> serde_json::Value {
+        serde_json::json!({
+            "game": {
+                "id": "abcdefgh",
+                "variant": { "key": "standard", "name": "Standard", "short": "Std" },
+                "speed": "blitz",
+                "perf": "blitz",
+                "rated": true,
+                "fen": "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+                "player": if turn == Color::white { "white" } else { "black" },
+                "turns": 0,
+                "startedAtTurn": 0,
+                "source": "lobby",
+                "status": { "id": status, "name": "started" },
+            },
+            "url": { "socket": "/play/abcdefgh/v6", "round": "/abcdefgh" },
+            "player": { "color": if player == Color::white { "white" } else { "black" }, "spectator": spectator },
+            "opponent": { "color": if player == Color::white { "black" } else { "white" } },
+        })
+    }
+
+    #[test]
+    fn movable() {
+        assert!(pov(Color::white, Color::white, false, 20).movable());
+        assert!(!pov(Color::white, Color::black, false, 20).movable());
+        assert!(pov(Color::white, Color::black, false, 20).playing());
+        assert!(!pov(Color::white, Color::white, true, 20).movable());
+        assert!(!pov(Color::white, Color::white, true, 20).playing());
+        assert!(!pov(Color::white, Color::white, false, 30).movable());
+    }
+
+    #[test]
+    fn possible_moves_as_string() {
+        let mut json = round(Color::white, Color::white, false, 20);
+        json["possibleMoves"] = serde_json::json!("e2e3e4 g1f3h3");
+        let pov: Pov = serde_json::from_value(json).unwrap();
+        let dests = pov.dests().unwrap();
+        assert_eq!(dests.targets("g1"), vec!("f3", "h3"));
+        assert!(dests.contains("e2", "e4"));
+    }
+
+    #[test]
+    fn double_pushes() {
+        let board = Board::from_fen("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR").unwrap();
+        assert_eq!(double_push(&board, "e2e4"), Square::parse("e3"));
+        let board = Board::from_fen("rnbqkbnr/pppppppp/8/8/8/4P3/PPPP1PPP/RNBQKBNR").unwrap();
+        assert_eq!(double_push(&board, "e2e3"), None);
+        let board = Board::from_fen("rnbqkbnr/pppppppp/8/8/8/5N2/PPPPPPPP/RNBQKB1R").unwrap();
+        assert_eq!(double_push(&board, "g1f3"), None);
+        assert_eq!(double_push(&board, "N@f3"), None);
+    }
+}
@@ -0,0 +1,284 @@
+//! Resolves moves in standard algebraic notation, like Nf3, exd5,
+//! O-O or e8=Q+, to the squares to move between in a position
+
+use std::fmt;
+
+use super::board::{Board, Role, Square};
+use super::movegen;
+use super::Dests;
+
+/// A move between two squares, like e2 to e4, as sent to lila
+#[derive(Debug, PartialEq)]
+pub struct Resolved {
+    pub from: String,
+    pub to: String,
+    pub promotion: Option<char>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum SanError {
+    Invalid,
+    Illegal,
+    Ambiguous,
+}
+
+impl fmt::Display for SanError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SanError::Invalid => write!(f, "not a move"),
+            SanError::Illegal => write!(f, "illegal here"),
+            SanError::Ambiguous => write!(f, "ambiguous, add the file or rank"),
+        }
+    }
+}
+
+enum Parsed {
+    Castle { long: bool },
+    Move {
+        role: Role,
+        file: Option<u8>,
+        rank: Option<u8>,
+        to: Square,
+        promotion: Option<char>,
+    },
+}
+
+/// Resolves by the rules of standard chess
+pub fn resolve(board: &Board, san: &str) -> Result<Resolved, SanError> {
+    let parsed = parse(san).ok_or(SanError::Invalid)?;
+    pick(board, parsed, movegen::legal_moves(board))
+}
+
+/// Resolves among the legal moves lila sent, for variants with other rules.
+/// Castling is the king moving onto its own rook, or two squares or more.
+pub fn resolve_dests(board: &Board, dests: &Dests, san: &str) -> Result<Resolved, SanError> {
+    let parsed = parse(san).ok_or(SanError::Invalid)?;
+    let mut moves = vec!();
+    for from in dests.origins().filter_map(Square::parse) {
+        let piece = match board.get(from) {
+            Some(piece) => piece,
+            None => continue,
+        };
+        for to in dests.targets(&from.to_string()).iter().filter_map(|s| Square::parse(s)) {
+            let onto_rook = board.get(to).is_some_and(|p| p.color == piece.color && p.role == Role::Rook);
+            let castle = piece.role == Role::King && (onto_rook || (to.file as i8 - from.file as i8).abs() > 1);
+            moves.push(movegen::Move { from, to, promotion: None, castle });
+        }
+    }
+    pick(board, parsed, moves)
+}
+
+/// A move without a known promotion matches any piece
+fn pick(board: &Board, parsed: Parsed, moves: Vec<movegen::Move>) -> Result<Resolved, SanError> {
+    match parsed {
+        Parsed::Castle { long } => moves.iter()
+            .filter(|m| m.castle && (m.to.file < m.from.file) == long)
+            // onto the rook is understood in every variant
+            .max_by_key(|m| board.get(m.to).is_some())
+            .map(|m| Resolved { from: m.from.to_string(), to: m.to.to_string(), promotion: None })
+            .ok_or(SanError::Illegal),
+        Parsed::Move { role, file, rank, to, promotion } => {
+            let mut candidates: Vec<Square> = moves.iter()
+                .filter(|m| !m.castle && m.to == to)
+                .filter(|m| board.get(m.from).is_some_and(|p| p.role == role))
+                .filter(|m| file.is_none_or(|f| f == m.from.file))
+                .filter(|m| rank.is_none_or(|r| r == m.from.rank))
+                .filter(|m| match promotion {
+                    Some(p) => m.promotion.is_none_or(|r| Some(r) == Role::from_char(p)),
+                    // the piece is picked later
+                    None => m.promotion.is_none_or(|r| r == Role::Queen),
+                })
+                .map(|m| m.from)
+                .collect();
+            candidates.dedup();
+            match candidates.as_slice() {
+                [from] => Ok(Resolved {
+                    from: from.to_string(),
+                    to: to.to_string(),
+                    promotion: promotion.map(|p| p.to_ascii_lowercase()),
+                }),
+                [] => Err(SanError::Illegal),
+                _ => Err(SanError::Ambiguous),
+            }
+        },
+    }
+}
+
+/// Reads a crazyhouse drop like N@f3, or @e6 for a pawn
+pub fn parse_drop(san: &str) -> Option<(Role, Square)> {
+    let (role, to) = san.trim_end_matches(['+', '#', '!', '?']).split_once('@')?;
+    let role = match role {
+        "" => Role::Pawn,
+        r if r.len() == 1 => Role::from_char(r.chars().next()?).filter(|&r| r != Role::King)?,
+        _ => return None,
+    };
+    Some((role, Square::parse(to)?))
+}
+
+fn parse(san: &str) -> Option<Parsed> {
+    let san = san.trim_end_matches(['+', '#', '!', '?']);
+    match san {
+        "O-O" | "0-0" => return Some(Parsed::Castle { long: false }),
+        "O-O-O" | "0-0-0" => return Some(Parsed::Castle { long: true }),
+        _ => (),
+    }
+    let mut chars: Vec<char> = san.chars().filter(|&c| c != 'x' && c != '=').collect();
+    let role = match chars.first() {
+        Some(&p) if "KQRBN".contains(p) => {
+            chars.remove(0);
+            Role::from_char(p)?
+        },
+        _ => Role::Pawn,
+    };
+    let promotion = match chars.last() {
+        Some(&p) if role == Role::Pawn && "QRBNK".contains(p.to_ascii_uppercase()) && !('a'..='h').contains(&p) => {
+            chars.pop();
+            Some(p)
+        },
+        _ => None,
+    };
+    if chars.len() < 2 || chars.len() > 4 {
+        return None;
+    }
+    let to: String = chars[chars.len() - 2..].iter().collect();
+    let to = Square::parse(&to)?;
+    let (mut file, mut rank) = (None, None);
+    for &c in &chars[..chars.len() - 2] {
+        match c {
+            'a'..='h' => file = Some(c as u8 - b'a'),
+            '1'..='8' => rank = Some(c as u8 - b'1'),
+            _ => return None,
+        }
+    }
+    Some(Parsed::Move { role, file, rank, to, promotion })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const START: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+    fn resolve(fen: &str, san: &str) -> Result<Resolved, SanError> {
+        super::resolve(&Board::from_fen(fen).unwrap(), san)
+    }
+
+    fn resolved(from: &str, to: &str, promotion: Option<char>) -> Result<Resolved, SanError> {
+        Ok(Resolved { from: from.into(), to: to.into(), promotion })
+    }
+
+    #[test]
+    fn pawn_pushes() {
+        assert_eq!(resolve(START, "e4"), resolved("e2", "e4", None));
+        assert_eq!(resolve(START, "e3"), resolved("e2", "e3", None));
+        assert_eq!(resolve(START, "e5"), Err(SanError::Illegal));
+        let black = "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1";
+        assert_eq!(resolve(black, "d5"), resolved("d7", "d5", None));
+    }
+
+    #[test]
+    fn pieces() {
+        assert_eq!(resolve(START, "Nf3"), resolved("g1", "f3", None));
+        assert_eq!(resolve(START, "Nc3+"), resolved("b1", "c3", None));
+        assert_eq!(resolve(START, "Bc4"), Err(SanError::Illegal));
+        assert_eq!(resolve(START, "Nd2"), Err(SanError::Illegal));
+    }
+
+    #[test]
+    fn captures() {
+        let fen = "rnbqkbnr/ppp1pppp/8/3p4/4P3/8/PPPP1PPP/RNBQKBNR w KQkq d6 0 2";
+        assert_eq!(resolve(fen, "exd5"), resolved("e4", "d5", None));
+        assert_eq!(resolve(fen, "Qg4"), resolved("d1", "g4", None));
+        let en_passant = "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3";
+        assert_eq!(resolve(en_passant, "exf6"), resolved("e5", "f6", None));
+        assert_eq!(resolve(en_passant, "exd6"), Err(SanError::Illegal));
+    }
+
+    #[test]
+    fn disambiguation() {
+        let fen = "4k3/8/8/8/8/8/8/R3K2R w - - 0 1";
+        assert_eq!(resolve(fen, "Rd1"), resolved("a1", "d1", None));
+        assert_eq!(resolve(fen, "Rf1"), resolved("h1", "f1", None));
+        let knights = "4k3/8/8/8/8/2N3N1/8/4K3 w - - 0 1";
+        assert_eq!(resolve(knights, "Ne4"), Err(SanError::Ambiguous));
+        assert_eq!(resolve(knights, "Nce4"), resolved("c3", "e4", None));
+        assert_eq!(resolve(knights, "Nge4"), resolved("g3", "e4", None));
+        let ranks = "4k3/8/8/8/2N5/8/2N5/4K3 w - - 0 1";
+        assert_eq!(resolve(ranks, "Ne3"), Err(SanError::Ambiguous));
+        assert_eq!(resolve(ranks, "N4e3"), resolved("c4", "e3", None));
+    }
+
+    #[test]
+    fn pinned_pieces_are_not_ambiguous() {
+        let fen = "4k3/4r3/8/8/2N5/4N3/8/4K3 w - - 0 1";
+        assert_eq!(resolve(fen, "Nd2"), resolved("c4", "d2", None));
+        assert_eq!(resolve(fen, "Nd5"), Err(SanError::Illegal));
+    }
+
+    #[test]
+    fn promotions() {
+        let fen = "8/4P1k1/8/8/8/8/8/4K3 w - - 0 1";
+        assert_eq!(resolve(fen, "e8=Q+"), resolved("e7", "e8", Some('q')));
+        assert_eq!(resolve(fen, "e8N"), resolved("e7", "e8", Some('n')));
+        assert_eq!(resolve(fen, "e8"), resolved("e7", "e8", None));
+    }
+
+    #[test]
+    fn castling() {
+        let fen = "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1";
+        assert_eq!(resolve(fen, "O-O"), resolved("e1", "h1", None));
+        assert_eq!(resolve(fen, "O-O-O"), resolved("e1", "a1", None));
+        assert_eq!(resolve(&fen.replace(" w ", " b "), "0-0"), resolved("e8", "h8", None));
+        assert_eq!(resolve(&fen.replace("KQkq", "Qkq"), "O-O"), Err(SanError::Illegal));
+        let checked = "r3k2r/8/8/8/8/8/5r2/R3K2R w KQkq - 0 1";
+        assert_eq!(resolve(checked, "O-O"), Err(SanError::Illegal));
+        let chess960 = "1r2k1r1/pppppppp/8/8/8/8/PPPPPPPP/1R2K1R1 w GBgb - 0 1";
+        assert_eq!(resolve(chess960, "O-O"), resolved("e1", "g1", None));
+        assert_eq!(resolve(chess960, "O-O-O"), resolved("e1", "b1", None));
+    }
+
+    fn dests(json: &str) -> Dests {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn with_dests() {
+        // a king may walk into check in antichess
+        let board = Board::from_fen("3r4/8/8/8/8/8/8/4K3 w - - 0 1").unwrap();
+        assert_eq!(resolve_dests(&board, &dests(r#"{"e1":"d1d2e2f1f2"}"#), "Kd1"), resolved("e1", "d1", None));
+        assert_eq!(super::resolve(&board, "Kd1"), Err(SanError::Illegal));
+        assert_eq!(resolve_dests(&board, &dests(r#"{"e1":"d1"}"#), "Kf1"), Err(SanError::Illegal));
+        let promotion = Board::from_fen("8/4P3/8/8/8/8/8/8 w - - 0 1").unwrap();
+        assert_eq!(resolve_dests(&promotion, &dests(r#"{"e7":"e8"}"#), "e8=K"), resolved("e7", "e8", Some('k')));
+        let knights = Board::from_fen("4k3/8/8/8/8/2N3N1/8/4K3 w - - 0 1").unwrap();
+        let both = dests(r#"{"c3":"e4","g3":"e4"}"#);
+        assert_eq!(resolve_dests(&knights, &both, "Ne4"), Err(SanError::Ambiguous));
+        assert_eq!(resolve_dests(&knights, &both, "Nge4"), resolved("g3", "e4", None));
+    }
+
+    #[test]
+    fn castling_with_dests() {
+        let board = Board::from_fen("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1").unwrap();
+        let castles = dests(r#"{"e1":"d1f1g1h1c1a1"}"#);
+        assert_eq!(resolve_dests(&board, &castles, "O-O"), resolved("e1", "h1", None));
+        assert_eq!(resolve_dests(&board, &castles, "O-O-O"), resolved("e1", "a1", None));
+        assert_eq!(resolve_dests(&board, &dests(r#"{"e1":"d1f1"}"#), "O-O"), Err(SanError::Illegal));
+    }
+
+    #[test]
+    fn drops() {
+        assert_eq!(parse_drop("N@f3"), Some((Role::Knight, Square::parse("f3").unwrap())));
+        assert_eq!(parse_drop("P@e6+"), Some((Role::Pawn, Square::parse("e6").unwrap())));
+        assert_eq!(parse_drop("@e6"), Some((Role::Pawn, Square::parse("e6").unwrap())));
+        assert_eq!(parse_drop("K@e6"), None);
+        assert_eq!(parse_drop("N@i9"), None);
+        assert_eq!(parse_drop("Nf3"), None);
+    }
+
+    #[test]
+    fn invalid() {
+        assert_eq!(resolve(START, "hello"), Err(SanError::Invalid));
+        assert_eq!(resolve(START, "Ni9"), Err(SanError::Invalid));
+        assert_eq!(resolve(START, ""), Err(SanError::Invalid));
+    }
+}
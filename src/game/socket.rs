@@ -1,29 +1,44 @@
 use serde_derive::Serialize;
 use serde_json::{json};
 
-use ws;
-
 use std;
-use std::rc::Rc;
-use std::cell::Cell;
-use std::sync::mpsc;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
-
-use std::thread;
+use std::time::Duration;
 
 use log::{error, warn, debug, trace};
 
 use cookie::CookieJar;
 
-use time;
+use futures::{SinkExt, StreamExt};
+
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio::time::{sleep_until, Instant};
+
+use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+use tokio_tungstenite::tungstenite;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::http::HeaderValue;
+use tokio_tungstenite::tungstenite::protocol::CloseFrame;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+
+use crate::lila;
 
 use serde_json;
 
 use url;
-use ws::{Request, Handler, Sender, Result, Message, Handshake, CloseCode, Error};
-use ws::util::{Token};
 
-const PING: Token = Token(1);
+/// Time between a pong and the next ping
+const PING_INTERVAL: Duration = Duration::from_secs(2);
+/// Reconnect when there is no pong by then
+const PONG_TIMEOUT: Duration = Duration::from_secs(9);
+/// Give up on a handshake that takes longer, and try again
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+type Stream = WebSocketStream<MaybeTlsStream<TcpStream>>;
 
 #[derive(Serialize, Debug)]
 struct PingPacket {
@@ -45,25 +60,241 @@ impl PingPacket {
 }
 
 
+/// What the socket tells the game about,
+/// besides the messages from lila
+pub enum Event {
+    Message(serde_json::Value),
+    Connected { reconnected: bool },
+    Disconnected,
+    /// Versioned messages were missed, the game should be refetched
+    Gap,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Status {
+    Connecting,
+    Connected,
+    Reconnecting { attempt: u32 },
+}
+
+/// One socket to lila, driven by a single task calling `next_event`.
+/// Keeps reconnecting with backoff until the sender of outgoing messages
+/// is dropped, then the connection is closed normally.
 pub struct Client {
-    out: Sender,
-    version: Rc<Cell<u64>>,
-    game_tx: mpsc::Sender<serde_json::Value>,
-    last_ping: time::OffsetDateTime,
+    url: String,
     cookie: CookieJar,
+    version: Arc<AtomicU64>,
+    send_rx: mpsc::UnboundedReceiver<String>,
+    status: Arc<Mutex<Status>>,
+    stream: Option<Stream>,
+    events: VecDeque<Event>,
+    attempt: u32,
+    reconnected: bool,
+    last_ping: Instant,
+    next_ping: Instant,
+    awaiting_pong: bool,
+    /// Expected version when a gap was reported, to report it only once
+    gap_at: Option<u64>,
+}
+
+enum Polled {
+    Open,
+    Closed,
+    Cancelled,
 }
 
-impl Handler for Client {
+impl Client {
+    /// The url should include the sri, which stays the same on every
+    /// attempt, while the last seen version is added to each
+    pub fn new(cookie: CookieJar, url: String, version: Arc<AtomicU64>,
+               send_rx: mpsc::UnboundedReceiver<String>,
+               status: Arc<Mutex<Status>>) -> Client {
+        let now = Instant::now();
+        Client {
+            url,
+            cookie,
+            version,
+            send_rx,
+            status,
+            stream: None,
+            events: VecDeque::new(),
+            attempt: 0,
+            reconnected: false,
+            last_ping: now,
+            next_ping: now,
+            awaiting_pong: false,
+            gap_at: None,
+        }
+    }
+
+    /// Waits for the next event, connecting when needed,
+    /// None means the game is done with the socket
+    pub async fn next_event(&mut self) -> Option<Event> {
+        loop {
+            if let Some(event) = self.events.pop_front() {
+                return Some(event);
+            }
+            match self.stream.take() {
+                None => {
+                    if !self.connect().await {
+                        return None;
+                    }
+                },
+                Some(mut stream) => match self.poll(&mut stream).await {
+                    Polled::Open => self.stream = Some(stream),
+                    Polled::Closed => self.disconnected(),
+                    Polled::Cancelled => {
+                        debug!("Closing socket");
+                        let frame = CloseFrame { code: CloseCode::Normal, reason: "".into() };
+                        if let Err(e) = stream.close(Some(frame)).await {
+                            debug!("Could not close socket: {}", e);
+                        }
+                        return None;
+                    },
+                },
+            }
+        }
+    }
+
+    /// Waits for the backoff, then tries to connect once.
+    /// Returns false when cancelled while waiting.
+    async fn connect(&mut self) -> bool {
+        if self.attempt > 0 {
+            let deadline = Instant::now() + backoff(self.attempt);
+            debug!("Reconnecting in {:?}", deadline - Instant::now());
+            loop {
+                tokio::select! {
+                    _ = sleep_until(deadline) => break,
+                    msg = self.send_rx.recv() => match msg {
+                        Some(msg) => warn!("Not connected, dropping: {}", msg),
+                        None => return false,
+                    },
+                }
+            }
+        }
+        let url = format!("{}&v={}", self.url, self.version.load(Ordering::SeqCst));
+        debug!("connecting to: {}", url);
+        match self.request(&url) {
+            Ok(request) => match self.handshake(request).await {
+                None => return false,
+                Some(Ok(stream)) => {
+                    *self.status.lock().unwrap() = Status::Connected;
+                    self.events.push_back(Event::Connected { reconnected: self.reconnected });
+                    self.stream = Some(stream);
+                    self.attempt = 0;
+                    self.reconnected = true;
+                    self.awaiting_pong = false;
+                    self.next_ping = Instant::now();
+                    self.gap_at = None;
+                    return true;
+                },
+                Some(Err(e)) => error!("Could not connect: {}", e),
+            },
+            Err(e) => error!("Invalid socket request: {}", e),
+        }
+        self.attempt += 1;
+        *self.status.lock().unwrap() = Status::Reconnecting { attempt: self.attempt };
+        true
+    }
+
+    /// Connects unless the sender is dropped first, None then,
+    /// or the server takes longer than `CONNECT_TIMEOUT` to answer
+    async fn handshake(&mut self, request: tungstenite::handshake::client::Request) -> Option<Result<Stream, String>> {
+        let deadline = Instant::now() + CONNECT_TIMEOUT;
+        let handshake = connect_async(request);
+        tokio::pin!(handshake);
+        loop {
+            tokio::select! {
+                result = &mut handshake => return Some(result.map(|(stream, _)| stream).map_err(|e| e.to_string())),
+                _ = sleep_until(deadline) => return Some(Err("timed out".to_owned())),
+                msg = self.send_rx.recv() => match msg {
+                    Some(msg) => warn!("Not connected, dropping: {}", msg),
+                    None => return None,
+                },
+            }
+        }
+    }
+
+    fn disconnected(&mut self) {
+        self.attempt = 1;
+        *self.status.lock().unwrap() = Status::Reconnecting { attempt: self.attempt };
+        self.events.push_back(Event::Disconnected);
+    }
 
-    fn on_open(&mut self, _: Handshake) -> Result<()> {
-        self.out.timeout(10, PING)
+    fn request(&self, url: &str) -> Result<tungstenite::handshake::client::Request, String> {
+        let parsed = url::Url::parse(url).map_err(|e| e.to_string())?;
+        let mut request = url.into_client_request().map_err(|e| e.to_string())?;
+        let headers = request.headers_mut();
+        let agent = format!("liru/{}", crate::VERSION);
+        headers.insert("User-Agent", HeaderValue::from_str(&agent).map_err(|e| e.to_string())?);
+        if let Some(cookie) = lila::cookies::header(&self.cookie, &parsed) {
+            headers.insert("Cookie", HeaderValue::from_str(&cookie).map_err(|e| e.to_string())?);
+        }
+        debug!("Built request: {:?}", request);
+        Ok(request)
+    }
+
+    /// Handles whatever happens first of an incoming message,
+    /// an outgoing message, or that it's time to ping
+    async fn poll(&mut self, stream: &mut Stream) -> Polled {
+        tokio::select! {
+            msg = stream.next() => match msg {
+                Some(Ok(Message::Text(text))) => {
+                    self.on_message(&text);
+                    Polled::Open
+                },
+                Some(Ok(Message::Close(frame))) => {
+                    debug!("Closed by server: {:?}", frame);
+                    Polled::Closed
+                },
+                Some(Ok(_)) => Polled::Open,
+                Some(Err(e)) => {
+                    error!("The socket encountered an error: {}", e);
+                    Polled::Closed
+                },
+                None => Polled::Closed,
+            },
+            msg = self.send_rx.recv() => match msg {
+                Some(msg) => {
+                    debug!("Sending: {}", msg);
+                    match stream.send(Message::Text(msg)).await {
+                        Ok(()) => Polled::Open,
+                        Err(e) => {
+                            error!("Could not send: {}", e);
+                            Polled::Closed
+                        },
+                    }
+                },
+                None => Polled::Cancelled,
+            },
+            _ = sleep_until(self.next_ping) => {
+                if self.awaiting_pong {
+                    warn!("No pong in {:?}, reconnecting", PONG_TIMEOUT);
+                    return Polled::Closed;
+                }
+                let ping = PingPacket::new(self.version.load(Ordering::SeqCst)).to_message();
+                self.last_ping = Instant::now();
+                self.next_ping = self.last_ping + PONG_TIMEOUT;
+                self.awaiting_pong = true;
+                match stream.send(Message::Text(ping)).await {
+                    Ok(()) => Polled::Open,
+                    Err(e) => {
+                        error!("Could not ping: {}", e);
+                        Polled::Closed
+                    },
+                }
+            },
+        }
     }
 
     /// Takes care of low level messages. For example
     /// ping/pong responses, and splitting batch messages.
-    fn on_message(&mut self, msg: Message) -> Result<()> {
+    fn on_message(&mut self, msg: &str) {
         trace!("Received str: {}", msg);
-        let json: serde_json::Value = serde_json::from_str(msg.as_text().unwrap()).unwrap();
+        let json: serde_json::Value = match serde_json::from_str(msg) {
+            Ok(json) => json,
+            Err(_) => return warn!("Ignoring invalid message: {}", msg),
+        };
         debug!("Received obj: {:?}", json);
         if json.is_object() {
             let obj = json.as_object().unwrap();
@@ -71,14 +302,15 @@ impl Handler for Client {
                 Some("n") => { // pong, inject travel time
                     let pong = json!({
                         "t": "n",
-                        "d": { "latency": self.milliseconds_since_ping() },
+                        "d": { "latency": self.last_ping.elapsed().as_millis() as i64 },
                     });
                     self.on_handle(&pong);
-                    self.out.timeout(2000, PING).unwrap();
+                    self.awaiting_pong = false;
+                    self.next_ping = Instant::now() + PING_INTERVAL;
                 }
                 Some("b") => { // batch
-                    let data = obj.get("d").unwrap();
-                    for item in data.as_array().unwrap().iter() {
+                    let items = obj.get("d").and_then(|d| d.as_array());
+                    for item in items.into_iter().flatten() {
                         self.on_handle(item);
                     }
                 },
@@ -87,97 +319,143 @@ impl Handler for Client {
         } else {
             self.on_handle(&json);
         }
-        Ok(())
     }
 
-    fn on_timeout(&mut self, event: Token) -> Result<()> {
-        match event {
-            PING => {
-                let ping = PingPacket::new(self.version.get()).to_message();
-                self.last_ping = time::OffsetDateTime::now_utc();
-                self.out.send(ping)
-            }
-            _ => Ok(error!("Invalid timeout token encountered!")),
-        }
+    fn on_handle(&mut self, obj: &serde_json::Value) {
+        // If message is versioned, it must have the expected version
+        let event = match obj.get("v").map_or(Ok(()), |v| self.update_version(v.as_u64())) {
+            Ok(()) => Event::Message(obj.to_owned()),
+            Err(VersionError::Gap(e)) => {
+                let expected = self.version.load(Ordering::SeqCst) + 1;
+                if self.gap_at == Some(expected) {
+                    return debug!("Dropping message, already resyncing. {}", e);
+                }
+                warn!("{}", e);
+                self.gap_at = Some(expected);
+                Event::Gap
+            },
+            Err(VersionError::Unexpected(e)) => {
+                return debug!("Dropping unexpected message. {}", e);
+            },
+        };
+        self.events.push_back(event);
     }
 
-    fn on_close(&mut self, code: CloseCode, reason: &str) {
-        debug!("on_close");
-        match code {
-            CloseCode::Normal => debug!("The client is done with the connection."),
-            CloseCode::Away   => debug!("The client is leaving the site."),
-            CloseCode::Abnormal => warn!(
-                "Closing handshake failed! Unable to obtain closing status from client."),
-                _ => error!("The client encountered an error: {}", reason),
+    fn update_version(&mut self, version: Option<u64>) -> std::result::Result<(), VersionError> {
+        let expected = self.version.load(Ordering::SeqCst) + 1;
+        match version {
+            Some(v) if (v < expected) => Err(VersionError::Unexpected(format!("Already has event {}", v))),
+            Some(v) if (v > expected) => Err(VersionError::Gap(
+                format!("Event gap detected, expected {} but got {}", expected, v))),
+            Some(v) => {
+                self.version.store(v, Ordering::SeqCst);
+                Ok(())
+            },
+            None => Err(VersionError::Unexpected("Version value is not u64".into())),
         }
     }
+}
 
-    fn on_error(&mut self, err: Error) {
-        error!("The server encountered an error: {:?}", err);
-    }
+enum VersionError {
+    Unexpected(String),
+    Gap(String),
+}
+
+/// Exponential delay before the next connection attempt,
+/// starting at half a second and capped at half a minute
+pub fn backoff(attempt: u32) -> Duration {
+    let millis = 500u64.saturating_mul(1 << attempt.saturating_sub(1).min(16));
+    Duration::from_millis(millis.min(30_000))
+}
 
-    fn build_request(&mut self, url: &url::Url) -> Result<Request> {
-        debug!("Handler is building request from {}.", url);
-        let mut r = Request::from_url(url)?;
-        r.headers_mut().push(("User-Agent".into(), format!("liru/{}", crate::VERSION).into()));
-        r.headers_mut().push(("Cookie".into(), format!("{:?}", self.cookie).into()));
-        debug!("Built request: {:?}", r);
-        Ok(r)
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+
+    use cookie::Cookie;
+
+    fn client(url: String, jar: CookieJar) -> (Client, mpsc::UnboundedSender<String>) {
+        let (send_tx, send_rx) = mpsc::unbounded_channel();
+        let status = Arc::new(Mutex::new(Status::Connecting));
+        (Client::new(jar, url, Arc::new(AtomicU64::new(0)), send_rx, status), send_tx)
     }
 
-}
+    #[test]
+    fn connect_sends_cookie_header() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("ws://{}/play/abc/v6?sri=test", listener.local_addr().unwrap());
+        let mut jar = CookieJar::new();
+        jar.add_original(Cookie::parse("lila2=abc; Path=/").unwrap());
+        jar.add_original(Cookie::parse("other=1; Domain=lichess.org").unwrap());
+        let (mut client, send_tx) = client(url, jar);
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let task = runtime.spawn(async move { client.next_event().await.is_none() });
 
-impl Client {
-    pub fn connect(c: &CookieJar, url: String, version: u64,
-                   game_tx: mpsc::Sender<serde_json::Value>,
-                   send_rx: Arc<Mutex<mpsc::Receiver<String>>>) {
-        debug!("connecting to: {}", url.clone());
-        let v = Rc::new(Cell::new(version));
-
-        ws::connect(url.to_owned(), |out| {
-            {
-                let out2 = out.clone();
-                let send_rx = send_rx.clone();
-                thread::spawn(move || {
-                    let send_rx = send_rx.lock().unwrap();
-                    loop {
-                        let msg = send_rx.recv().unwrap();
-                        debug!("Sending: {}", msg);
-                        out2.send(msg).unwrap();
-                    };
-                });
-            }
-            Client {
-                out: out.clone(),
-                version: v.clone(),
-                game_tx: game_tx.clone(),
-                last_ping: time::OffsetDateTime::now_utc(),
-                cookie: c.clone(),
-            }
-        }).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let headers: Vec<String> = BufReader::new(stream)
+            .lines()
+            .map(|line| line.unwrap())
+            .take_while(|line| !line.is_empty())
+            .collect();
+        assert_eq!(headers[0], "GET /play/abc/v6?sri=test&v=0 HTTP/1.1");
+        let cookies: Vec<String> = headers.iter()
+            .map(|h| h.to_ascii_lowercase())
+            .filter(|h| h.starts_with("cookie:"))
+            .collect();
+        assert_eq!(cookies, vec!("cookie: lila2=abc"));
+        // the stream is dropped with the reader, so the handshake
+        // fails and the client is waiting to reconnect
+        drop(send_tx);
+        let cancelled = runtime.block_on(async {
+            tokio::time::timeout(Duration::from_secs(5), task).await
+        });
+        assert!(cancelled.unwrap().unwrap());
     }
 
-    fn on_handle(&mut self, obj: &serde_json::Value) {
-        // If message is versioned, it must have the expected version
-        match obj.get("v").map_or(Ok(()), |v| self.update_version(v.as_u64())) {
-            Ok(()) => self.game_tx.send(obj.to_owned()).unwrap(),
-            Err(e) => debug!("Dropping unexpected message. {}", e),
-        }
+    #[test]
+    fn dropping_sender_cancels() {
+        // nothing listens here, so the client is waiting to reconnect
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("ws://{}/play/abc/v6?sri=test", listener.local_addr().unwrap());
+        drop(listener);
+        let (mut client, send_tx) = client(url, CookieJar::new());
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let task = runtime.spawn(async move { client.next_event().await.is_none() });
+        std::thread::sleep(Duration::from_millis(50));
+        drop(send_tx);
+        let cancelled = runtime.block_on(async {
+            tokio::time::timeout(Duration::from_secs(5), task).await
+        });
+        assert!(cancelled.unwrap().unwrap());
     }
 
-    fn update_version(&mut self, version: Option<u64>) -> std::result::Result<(), String> {
-        let expected = self.version.get() + 1;
-        match version {
-            Some(v) if (v < expected) => Err(format!("Already has event {}", v)),
-            Some(v) if (v > expected) =>
-                Err(format!("Event gap detected, expected {} but got {}", expected, v)),
-            Some(v) => Ok(self.version.set(v)),
-            None => Err("Version value is not u64".into()),
-        }
+    #[test]
+    fn dropping_sender_cancels_handshake() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("ws://{}/play/abc/v6?sri=test", listener.local_addr().unwrap());
+        let (mut client, send_tx) = client(url, CookieJar::new());
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let task = runtime.spawn(async move { client.next_event().await.is_none() });
+        // accepted but never answered, the handshake stalls
+        let (_stream, _) = listener.accept().unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        drop(send_tx);
+        let cancelled = runtime.block_on(async {
+            tokio::time::timeout(Duration::from_secs(5), task).await
+        });
+        assert!(cancelled.unwrap().unwrap());
     }
 
-    fn milliseconds_since_ping(&self) -> i128 {
-        (time::OffsetDateTime::now_utc() - self.last_ping).whole_milliseconds()
+    #[test]
+    fn backoff_grows() {
+        assert_eq!(backoff(1), Duration::from_millis(500));
+        assert_eq!(backoff(2), Duration::from_millis(1000));
+        assert_eq!(backoff(3), Duration::from_millis(2000));
+        assert_eq!(backoff(6), Duration::from_millis(16000));
+        assert_eq!(backoff(7), Duration::from_millis(30000));
+        assert_eq!(backoff(100), Duration::from_millis(30000));
     }
 }
-
@@ -0,0 +1,115 @@
+//! What sets the variants lila has apart, for showing the game
+
+use serde_derive::Deserialize;
+
+use super::board::{Board, Square};
+
+/// Number of checks that win in three-check
+pub const CHECKS_TO_WIN: u32 = 3;
+
+#[derive(Deserialize)]
+pub struct Variant {
+    pub key: String,
+    pub name: String,
+    pub short: String,
+}
+
+impl Variant {
+    /// How the game is won, shown in the game header
+    pub fn win_condition(&self) -> &'static str {
+        match self.key.as_str() {
+            "crazyhouse" => "Checkmate, taken pieces can be dropped",
+            "kingOfTheHill" => "Checkmate, or bring the king to the center",
+            "threeCheck" => "Checkmate, or check the king three times",
+            "antichess" => "Lose every piece or get stalemated, taking is forced",
+            "atomic" => "Explode or checkmate the king",
+            "horde" => "White mates, black takes every pawn",
+            "racingKings" => "Bring the king to the 8th rank first",
+            _ => "Checkmate the king",
+        }
+    }
+
+    /// What the variant end status means in this variant
+    pub fn end_reason(&self) -> Option<&'static str> {
+        match self.key.as_str() {
+            "kingOfTheHill" => Some("King in the center"),
+            "threeCheck" => Some("Three checks"),
+            "antichess" => Some("Lose all your pieces"),
+            "atomic" => Some("Explosion"),
+            "horde" => Some("Every pawn taken"),
+            "racingKings" => Some("Race finished"),
+            _ => None,
+        }
+    }
+
+    /// Squares the king races to, the hill in king of the
+    /// hill and the 8th rank in racing kings
+    pub fn goal(&self) -> Vec<Square> {
+        match self.key.as_str() {
+            "kingOfTheHill" => ["d4", "e4", "d5", "e5"].iter().filter_map(|s| Square::parse(s)).collect(),
+            "racingKings" => (0..8).map(|f| Square::new(f, 7)).collect(),
+            _ => vec!(),
+        }
+    }
+}
+
+/// Squares cleared by a capture in atomic, found by comparing the position
+/// before and after, the captured square and its neighbours but pawns.
+/// The capturing piece left its own square, which didn't explode.
+pub fn explosion(before: &Board, after: &Board, from: Square, to: Square) -> Vec<Square> {
+    if before.get(to).is_none() && before.en_passant != Some(to) {
+        return vec!();
+    }
+    let blast = (-1..=1).flat_map(|f| (-1..=1).map(move |r| (f, r)));
+    blast.filter_map(|(f, r)| to.offset(f, r))
+        .filter(|&s| s == to || s != from && before.get(s).is_some() && after.get(s).is_none())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::game::Color;
+
+    fn variant(key: &str) -> Variant {
+        Variant { key: key.into(), name: key.into(), short: key.into() }
+    }
+
+    #[test]
+    fn goals() {
+        assert_eq!(variant("standard").goal(), vec!());
+        assert_eq!(variant("kingOfTheHill").goal().len(), 4);
+        assert!(variant("racingKings").goal().contains(&Square::parse("h8").unwrap()));
+    }
+
+    #[test]
+    fn explosions() {
+        let before = Board::from_fen("rnbqkbnr/ppp2ppp/8/3pp3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 0 3").unwrap();
+        // Nxe5 takes the pawn and the knight goes with it, pawns around stay
+        let after = Board::from_fen("rnbqkbnr/ppp2ppp/8/3p4/4P3/8/PPPP1PPP/RNBQKB1R b KQkq - 0 3").unwrap();
+        let (f3, e5) = (Square::parse("f3").unwrap(), Square::parse("e5").unwrap());
+        assert_eq!(explosion(&before, &after, f3, e5), vec!(e5));
+        let quiet = Board::from_fen("rnbqkbnr/ppp2ppp/8/3pp3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 0 3").unwrap();
+        assert_eq!(explosion(&quiet, &quiet, Square::parse("e2").unwrap(), Square::parse("e3").unwrap()), vec!());
+        let crowded = Board::from_fen("4k3/8/8/3nbr2/4p3/8/8/4K1N1 w - - 0 1").unwrap();
+        let blown = Board::from_fen("4k3/8/8/8/8/8/8/4K3 b - - 0 1").unwrap();
+        let e4 = Square::parse("e4").unwrap();
+        let squares: Vec<String> = explosion(&crowded, &blown, Square::parse("g1").unwrap(), e4).iter().map(|s| s.to_string()).collect();
+        assert_eq!(squares, vec!("d5", "e4", "e5", "f5"));
+        // Qd4xe5, the queen's square is next to the blast but only emptied by the move
+        let adjacent = Board::from_fen("4k3/8/5n2/4p3/3Q4/8/8/4K3 w - - 0 1").unwrap();
+        let exploded = Board::from_fen("4k3/8/8/8/8/8/8/4K3 b - - 0 1").unwrap();
+        let squares: Vec<String> = explosion(&adjacent, &exploded, Square::parse("d4").unwrap(), e5).iter().map(|s| s.to_string()).collect();
+        assert_eq!(squares, vec!("e5", "f6"));
+    }
+
+    #[test]
+    fn starting_layouts() {
+        let horde = Board::from_fen("rnbqkbnr/pppppppp/8/1PP2PP1/PPPPPPPP/PPPPPPPP/PPPPPPPP/PPPPPPPP w kq - 0 1").unwrap();
+        assert_eq!(horde.king(Color::white), None);
+        assert!(horde.get(Square::parse("a1").unwrap()).is_some());
+        let racing = Board::from_fen("8/8/8/8/8/8/krbnNBRK/qrbnNBRQ w - - 0 1").unwrap();
+        assert_eq!(racing.king(Color::white), Square::parse("h2"));
+        assert_eq!(racing.king(Color::black), Square::parse("a2"));
+    }
+}
@@ -1,63 +1,126 @@
 use serde_derive::Deserialize;
 
-use std::str;
 use std::collections::HashMap;
+use std::future::Future;
+use std::ops::Deref;
+use std::sync::Arc;
 
-use hyper::{Body, Request};
-use hyper::client::Client;
+use hyper::{Body, HeaderMap, Method, Request};
+use hyper::client::{Client, HttpConnector};
 use hyper::header::{
+    AUTHORIZATION,
     CONTENT_LENGTH,
     ACCEPT,
-    CONNECTION,
     CONTENT_TYPE,
+    COOKIE,
     SET_COOKIE,
     USER_AGENT,
 };
 use hyper_tls::HttpsConnector;
+use tokio::task::JoinHandle;
 
 use serde_json;
 use url::form_urlencoded;
+use url::Url;
 
 use cookie::{Cookie, CookieJar};
 
+pub mod cookies;
+mod error;
+mod stored_session;
+
+pub use self::error::LilaError;
+use self::stored_session::StoredSession;
+
+/// Clones share the http client, with its connection pool, and
+/// the runtime, so they can be moved into spawned tasks
+#[derive(Clone)]
 pub struct Session {
     pub user: LilaUser,
     pub cookie: Box<CookieJar>,
+    pub server: Server,
+    token: Option<String>,
+    client: Client<HttpsConnector<HttpConnector>>,
+    runtime: Arc<Runtime>,
+}
+
+/// Shuts down without waiting, so the last clone of
+/// a session may be dropped inside one of its own tasks
+struct Runtime(Option<tokio::runtime::Runtime>);
+
+impl Deref for Runtime {
+    type Target = tokio::runtime::Runtime;
+    fn deref(&self) -> &tokio::runtime::Runtime {
+        self.0.as_ref().unwrap()
+    }
+}
+
+impl Drop for Runtime {
+    fn drop(&mut self) {
+        if let Some(runtime) = self.0.take() {
+            runtime.shutdown_background();
+        }
+    }
+}
+
+/// Base urls of the lila instance to talk to
+#[derive(Clone)]
+pub struct Server {
+    pub url: String,
+    pub socket_url: String,
+}
+
+impl Default for Server {
+    fn default() -> Server {
+        Server {
+            url: "https://lichess.org".to_owned(),
+            socket_url: "wss://socket3.lichess.org".to_owned(),
+        }
+    }
 }
 
 #[allow(non_snake_case)]
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 pub struct LilaUser {
     pub id: String,
     pub username: String,
+    #[serde(default)]
     pub online: bool,
     pub engine: Option<bool>,
     pub booster: Option<bool>,
     // pub profile :{"country":"SE"}
+    #[serde(default)]
     pub perfs: HashMap<String, Perf>,
     pub createdAt: i64,
+    #[serde(default)]
     pub seenAt: i64,
+    #[serde(default)]
     pub playTime: PlayTime,
+    #[serde(default)]
     pub nowPlaying: Vec<PlayingGame>,
 }
 
-#[derive(Deserialize, Debug)]
+/// Rated perfs have games and a rating, puzzle modes
+/// like storm, racer and streak only runs and a score
+#[derive(Deserialize, Debug, Clone)]
 pub struct Perf {
-    pub games: i64,
-    pub rating: i64,
-    pub rd: i64,
+    pub games: Option<i64>,
+    pub rating: Option<i64>,
+    pub rd: Option<i64>,
     pub prov: Option<bool>,
-    pub prog: i64,
+    pub prog: Option<i64>,
+    pub runs: Option<i64>,
+    pub score: Option<i64>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Default, Clone)]
 pub struct PlayTime {
     pub total: i64,
     pub tv: i64,
 }
 
 #[allow(non_snake_case)]
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 pub struct PlayingGame {
     pub fullId: String,
     pub gameId: String,
@@ -78,6 +141,12 @@ pub struct PlayingGame {
 
 #[allow(non_snake_case)]
 #[derive(Deserialize, Debug)]
+struct NowPlaying {
+    nowPlaying: Vec<PlayingGame>,
+}
+
+#[allow(non_snake_case)]
+#[derive(Deserialize, Debug, Clone)]
 pub struct PlayingOpponent {
     pub id: Option<String>,
     pub username: String,
@@ -85,7 +154,7 @@ pub struct PlayingOpponent {
 }
 
 impl Session {
-    pub fn anonymous() -> Session {
+    pub fn anonymous(server: Server) -> Session {
         let cookie = Box::new(CookieJar::new());
         Session {
             user: LilaUser {
@@ -104,87 +173,337 @@ impl Session {
                 nowPlaying: vec!(),
             },
             cookie: cookie,
+            server,
+            token: None,
+            client: Client::builder().build::<_, Body>(HttpsConnector::new()),
+            runtime: Arc::new(Runtime(Some(tokio::runtime::Runtime::new()
+                .expect("Could not start the tokio runtime")))),
         }
     }
 
-    pub fn url(path: &str) -> String {
-        let base_url = "https://lichess.org";
-        format!("{}/{}", base_url, path)
+    /// Signs in with a personal api token, which is sent
+    /// as a bearer token instead of using cookies
+    pub fn with_token(server: Server, token: String) -> Result<Session, LilaError> {
+        let mut session = Session::anonymous(server);
+        session.token = Some(token);
+        session.user = serde_json::from_str(&session.get("api/account")?)?;
+        let playing = session.get("api/account/playing")
+            .and_then(|body| Ok(serde_json::from_str::<NowPlaying>(&body)?));
+        match playing {
+            Ok(playing) => session.user.nowPlaying = playing.nowPlaying,
+            Err(e) => log::warn!("Could not get playing games: {}", e),
+        }
+        Ok(session)
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        self.server.url(path)
     }
 
-    pub fn socket_url(path: &str) -> String {
-        let base_url = "wss://socket3.lichess.org";
-        format!("{}{}", base_url, path)
+    pub fn socket_url(&self, path: &str) -> String {
+        self.server.socket_url(path)
     }
 
-    pub fn sign_in(username: String, password: String) -> Result<Session, &'static str> {
-        let https = HttpsConnector::new();
-        let client = Client::builder()
-            .build::<_, Body>(https);
+    pub fn sign_in(server: Server, username: String, password: String) -> Result<Session, LilaError> {
         let mut data = String::new();
         form_urlencoded::Serializer::new(&mut data)
             .append_pair("username", &username)
             .append_pair("password", &password);
-        let req = Request::builder()
-            .method("POST")
-            .uri(&Session::url("login"))
-            .header(CONTENT_LENGTH, data.len())
-            .header(CONNECTION, "close")
-            .header(USER_AGENT, format!("liru/{}", crate::VERSION).as_str())
-            .header(ACCEPT, "application/vnd.lichess.v1+json")
-            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
-            .body(data.into()).unwrap();
-
-        let res = tokio::runtime::Runtime::new()
-            .unwrap()
-            .block_on(async {
-                client.request(req).await.unwrap()
-            });
-        if res.status().is_success() {
-            let mut cookie_jar = CookieJar::new();
-            for cookie in res.headers().get_all(SET_COOKIE).iter() {
-                let cookie = cookie.to_str().unwrap().to_string();
-                cookie_jar.add_original(Cookie::parse(cookie).unwrap());
+        let mut session = Session::anonymous(server);
+        let (headers, body) = session.block_on(session.request(Method::POST, "login", data))
+            .map_err(|e| {
+                log::error!("Could not login: {}", e);
+                e
+            })?;
+        let mut cookie_jar = CookieJar::new();
+        for cookie in headers.get_all(SET_COOKIE).iter() {
+            match cookie.to_str().map(|c| Cookie::parse(c.to_string())) {
+                Ok(Ok(cookie)) => cookie_jar.add_original(cookie),
+                _ => log::warn!("Invalid cookie: {:?}", cookie),
             }
-            let b = tokio::runtime::Runtime::new()
-                .unwrap()
-                .block_on(async {
-                    hyper::body::to_bytes(res).await.unwrap()
-                });
-            let body = str::from_utf8(&b).unwrap();
-            log::trace!("{}", &body);
-            Ok(Session {
-                user: serde_json::from_str(&body).unwrap(),
-                cookie: Box::new(cookie_jar),
-            })
-            //} else if res.status.is_client_error() {
-        } else {
-            log::error!("Could not login: {}", res.status());
-            Err("Could not login")
         }
+        log::trace!("{}", &body);
+        session.user = serde_json::from_str(&body)?;
+        session.cookie = Box::new(cookie_jar);
+        Ok(session)
     }
 
-    pub fn get(&self, path: &str) -> String {
-        // TODO: catch error and print
-        let https = HttpsConnector::new();
-        let client = Client::builder()
-            .build::<_, Body>(https);
+    /// Reuses the session stored by `save`, if it's for
+    /// the same server and the cookies are still valid
+    pub fn restore(server: Server) -> Option<Session> {
+        let stored = StoredSession::load(&StoredSession::default_path()?)?;
+        if stored.server != server.url {
+            log::debug!("Stored session is for {}", stored.server);
+            return None;
+        }
+        let mut cookie_jar = CookieJar::new();
+        for cookie in stored.cookies {
+            match Cookie::parse(cookie) {
+                Ok(cookie) => cookie_jar.add_original(cookie),
+                Err(e) => log::warn!("Invalid stored cookie: {}", e),
+            }
+        }
+        let mut session = Session::anonymous(server);
+        session.cookie = Box::new(cookie_jar);
+        let user = session.get("account/info")
+            .and_then(|body| Ok(serde_json::from_str::<LilaUser>(&body)?));
+        match user {
+            Ok(user) if user.id == stored.user_id => {
+                session.user = user;
+                Some(session)
+            },
+            Ok(user) => {
+                log::warn!("Stored session belongs to {}", user.id);
+                None
+            },
+            Err(LilaError::Auth) => {
+                log::info!("Stored session is no longer valid");
+                None
+            },
+            Err(e) => {
+                log::warn!("Could not restore session: {}", e);
+                None
+            },
+        }
+    }
+
+    /// Stores the cookies so the next run can `restore` them
+    pub fn save(&self) {
+        let path = match StoredSession::default_path() {
+            Some(path) => path,
+            None => return log::warn!("Could not find a directory to store the session in"),
+        };
+        let stored = StoredSession {
+            server: self.server.url.clone(),
+            user_id: self.user.id.clone(),
+            cookies: self.cookie.iter().map(|c| c.to_string()).collect(),
+        };
+        if let Err(e) = stored.save(&path) {
+            log::error!("Could not store session {}: {}", path.display(), e);
+        }
+    }
+
+    /// Signs out the stored session on the server and deletes it
+    pub fn logout(server: Server) -> Result<(), String> {
+        let path = StoredSession::default_path()
+            .ok_or("Could not find the stored session")?;
+        if let Some(session) = Session::restore(server) {
+            match session.block_on(session.post_async("logout", String::new())) {
+                Ok(_) => log::debug!("Signed out {}", session.user.id),
+                Err(e) => log::warn!("Could not sign out {}: {}", session.user.id, e),
+            }
+        }
+        StoredSession::delete(&path)
+            .map_err(|e| format!("Could not delete {}: {}", path.display(), e))
+    }
+
+    /// Runs the future on the shared runtime, returning its handle
+    pub fn spawn<F>(&self, future: F) -> JoinHandle<F::Output>
+        where F: Future + Send + 'static,
+              F::Output: Send + 'static {
+        self.runtime.spawn(future)
+    }
 
+    /// Blocks until the future is done, must
+    /// not be called from inside a task
+    pub fn block_on<F: Future>(&self, future: F) -> F::Output {
+        self.runtime.block_on(future)
+    }
+
+    pub fn get(&self, path: &str) -> Result<String, LilaError> {
+        self.block_on(self.get_async(path))
+    }
+
+    pub async fn get_async(&self, path: &str) -> Result<String, LilaError> {
+        self.request(Method::GET, path, String::new()).await.map(|(_, body)| body)
+    }
+
+    pub async fn post_async(&self, path: &str, data: String) -> Result<String, LilaError> {
+        self.request(Method::POST, path, data).await.map(|(_, body)| body)
+    }
+
+    /// Sends the request and reads the whole body,
+    /// any status other than success is an error
+    async fn request(&self, method: Method, path: &str, data: String) -> Result<(HeaderMap, String), LilaError> {
+        let url = Url::parse(&self.url(path))
+            .map_err(|e| LilaError::Network(Box::new(e)))?;
         let mut builder = Request::builder();
-        builder = builder.method("GET")
-            .uri(&Session::url(path))
-            .header(CONNECTION, "close")
+        builder = builder.method(method.clone())
+            .uri(url.as_str())
             .header(USER_AGENT, format!("liru/{}", crate::VERSION).as_str())
             .header(ACCEPT, "application/vnd.lichess.v1+json");
-        for cookie in self.cookie.iter() {
-            builder = builder.header(cookie.name(), cookie.value());
-        }
-        let req = builder.body(Body::empty()).unwrap();
-        let res = tokio::runtime::Runtime::new()
-            .unwrap()
-            .block_on(async {
-                hyper::body::to_bytes(client.request(req).await.unwrap()).await.unwrap()
-            });
-        str::from_utf8(&res).unwrap().to_string()
+        if method == Method::POST {
+            builder = builder
+                .header(CONTENT_LENGTH, data.len())
+                .header(CONTENT_TYPE, "application/x-www-form-urlencoded");
+        }
+        if let Some(cookie) = cookies::header(&self.cookie, &url) {
+            builder = builder.header(COOKIE, cookie);
+        }
+        if let Some(ref token) = self.token {
+            builder = builder.header(AUTHORIZATION, format!("Bearer {}", token));
+        }
+        let req = builder.body(data.into())?;
+        let res = self.client.request(req).await?;
+        let (parts, body) = res.into_parts();
+        let body = hyper::body::to_bytes(body).await?;
+        if parts.status.is_success() {
+            Ok((parts.headers, String::from_utf8_lossy(&body).into_owned()))
+        } else {
+            Err(LilaError::from_status(parts.status))
+        }
+    }
+}
+
+impl Server {
+    /// Validates the given base urls, http and ws are allowed
+    /// for local instances. Without a socket url it's derived
+    /// from the http url, using the same host and port.
+    pub fn new(url: &str, socket_url: Option<&str>) -> Result<Server, String> {
+        let http = Url::parse(url).map_err(|e| format!("Invalid server url {}: {}", url, e))?;
+        if http.scheme() != "http" && http.scheme() != "https" {
+            return Err(format!("Server url must be http or https: {}", url));
+        }
+        let socket_url = match socket_url {
+            Some(socket_url) => {
+                let ws = Url::parse(socket_url)
+                    .map_err(|e| format!("Invalid socket url {}: {}", socket_url, e))?;
+                if ws.scheme() != "ws" && ws.scheme() != "wss" {
+                    return Err(format!("Socket url must be ws or wss: {}", socket_url));
+                }
+                socket_url.trim_end_matches('/').to_owned()
+            },
+            None if http.host_str() == Some("lichess.org") => Server::default().socket_url,
+            None => {
+                let scheme = if http.scheme() == "https" { "wss" } else { "ws" };
+                match http.port() {
+                    Some(port) => format!("{}://{}:{}", scheme, http.host_str().unwrap_or(""), port),
+                    None => format!("{}://{}", scheme, http.host_str().unwrap_or("")),
+                }
+            },
+        };
+        Ok(Server {
+            url: url.trim_end_matches('/').to_owned(),
+            socket_url,
+        })
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("{}/{}", self.url, path)
+    }
+
+    pub fn socket_url(&self, path: &str) -> String {
+        format!("{}{}", self.socket_url, path)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Accepts one request and answers it with an empty
+    /// json object, returning the received headers
+    fn stand_in_server() -> (Server, thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let headers: Vec<String> = BufReader::new(stream.try_clone().unwrap())
+                .lines()
+                .map(|line| line.unwrap())
+                .take_while(|line| !line.is_empty())
+                .collect();
+            stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\n{}").unwrap();
+            headers
+        });
+        (Server::new(&url, None).unwrap(), handle)
+    }
+
+    #[test]
+    fn account() {
+        let user: LilaUser = serde_json::from_str(r#"{
+            "id": "flugsio", "username": "flugsio", "online": true,
+            "perfs": {
+                "blitz": {"games": 1204, "rating": 1687, "rd": 52, "prog": -12},
+                "puzzle": {"games": 310, "rating": 1802, "rd": 81, "prog": 0, "prov": true},
+                "storm": {"runs": 14, "score": 31},
+                "racer": {"runs": 3, "score": 42},
+                "streak": {"runs": 7, "score": 18}
+            },
+            "createdAt": 1290415680000, "seenAt": 1700000000000,
+            "playTime": {"total": 1230000, "tv": 3600},
+            "url": "https://lichess.org/@/flugsio", "count": {"all": 1500}
+        }"#).unwrap();
+        let blitz = &user.perfs["blitz"];
+        assert_eq!((blitz.games, blitz.rating, blitz.rd, blitz.prog), (Some(1204), Some(1687), Some(52), Some(-12)));
+        assert_eq!(user.perfs["puzzle"].prov, Some(true));
+        let storm = &user.perfs["storm"];
+        assert_eq!((storm.runs, storm.score, storm.rating), (Some(14), Some(31), None));
+    }
+
+    #[test]
+    fn server_default() {
+        let server = Server::default();
+        assert_eq!(server.url("tv/blitz"), "https://lichess.org/tv/blitz");
+        assert_eq!(server.socket_url("/play/abc/v6"), "wss://socket3.lichess.org/play/abc/v6");
+    }
+
+    #[test]
+    fn server_lichess() {
+        let server = Server::new("https://lichess.org/", None).unwrap();
+        assert_eq!(server.url, "https://lichess.org");
+        assert_eq!(server.socket_url, "wss://socket3.lichess.org");
+    }
+
+    #[test]
+    fn server_local() {
+        let server = Server::new("http://localhost:9663", None).unwrap();
+        assert_eq!(server.url, "http://localhost:9663");
+        assert_eq!(server.socket_url, "ws://localhost:9663");
+        let server = Server::new("http://localhost:9663", Some("ws://localhost:9664/")).unwrap();
+        assert_eq!(server.socket_url, "ws://localhost:9664");
+    }
+
+    #[test]
+    fn server_self_hosted() {
+        let server = Server::new("https://chess.example.com", None).unwrap();
+        assert_eq!(server.socket_url, "wss://chess.example.com");
+    }
+
+    #[test]
+    fn get_sends_cookie_header() {
+        let (server, handle) = stand_in_server();
+        let mut session = Session::anonymous(server);
+        for cookie in &["lila2=abc; Path=/", "api=1; Path=/api", "other=1; Domain=lichess.org",
+                        "old=1; Expires=Wed, 21 Oct 2015 07:28:00 GMT"] {
+            session.cookie.add_original(Cookie::parse(cookie.to_string()).unwrap());
+        }
+        assert_eq!(session.get("tv/best").unwrap(), "{}");
+        let headers = handle.join().unwrap();
+        assert_eq!(headers[0], "GET /tv/best HTTP/1.1");
+        let cookies: Vec<&String> = headers.iter()
+            .filter(|h| h.to_ascii_lowercase().starts_with("cookie:"))
+            .collect();
+        assert_eq!(cookies, vec!("cookie: lila2=abc"));
+    }
+
+    #[test]
+    fn get_without_cookies() {
+        let (server, handle) = stand_in_server();
+        let session = Session::anonymous(server);
+        session.get("tv/best").unwrap();
+        let headers = handle.join().unwrap();
+        assert!(!headers.iter().any(|h| h.to_ascii_lowercase().starts_with("cookie:")));
+    }
+
+    #[test]
+    fn server_invalid() {
+        assert!(Server::new("lichess.org", None).is_err());
+        assert!(Server::new("ftp://lichess.org", None).is_err());
+        assert!(Server::new("https://lichess.org", Some("https://socket.lichess.org")).is_err());
     }
 }
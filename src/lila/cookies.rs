@@ -0,0 +1,115 @@
+use cookie::{Cookie, CookieJar};
+use url::Url;
+
+/// Serializes the cookies that apply to the url into the value
+/// of a Cookie header, or None if there are none to send
+pub fn header(jar: &CookieJar, url: &Url) -> Option<String> {
+    let now = time::OffsetDateTime::now_utc();
+    let cookies: Vec<String> = jar.iter()
+        .filter(|cookie| matches_domain(cookie, url)
+                && matches_path(cookie, url)
+                && matches_secure(cookie, url))
+        .filter(|cookie| !cookie.expires_datetime().is_some_and(|expires| expires <= now))
+        .filter(|cookie| !cookie.max_age().is_some_and(|max_age| max_age <= time::Duration::ZERO))
+        .map(|cookie| format!("{}={}", cookie.name(), cookie.value()))
+        .collect();
+    if cookies.is_empty() {
+        None
+    } else {
+        Some(cookies.join("; "))
+    }
+}
+
+/// Cookies without domain were set by the server we talk to
+fn matches_domain(cookie: &Cookie, url: &Url) -> bool {
+    match (cookie.domain(), url.host_str()) {
+        (None, _) => true,
+        (Some(domain), Some(host)) => {
+            let domain = domain.trim_start_matches('.').to_ascii_lowercase();
+            let host = host.to_ascii_lowercase();
+            host == domain || host.ends_with(&format!(".{}", domain))
+        },
+        (Some(_), None) => false,
+    }
+}
+
+fn matches_path(cookie: &Cookie, url: &Url) -> bool {
+    match cookie.path() {
+        None | Some("/") | Some("") => true,
+        Some(path) => {
+            let path = path.trim_end_matches('/');
+            url.path() == path || url.path().starts_with(&format!("{}/", path))
+        },
+    }
+}
+
+fn matches_secure(cookie: &Cookie, url: &Url) -> bool {
+    cookie.secure() != Some(true) || url.scheme() == "https" || url.scheme() == "wss"
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn jar(cookies: &[&str]) -> CookieJar {
+        let mut jar = CookieJar::new();
+        for cookie in cookies {
+            jar.add_original(Cookie::parse(cookie.to_string()).unwrap());
+        }
+        jar
+    }
+
+    fn url(url: &str) -> Url {
+        Url::parse(url).unwrap()
+    }
+
+    #[test]
+    fn header_empty() {
+        assert_eq!(header(&CookieJar::new(), &url("https://lichess.org/")), None);
+    }
+
+    #[test]
+    fn header_joins_name_value() {
+        let jar = jar(&["lila2=abc; Path=/; HttpOnly", "other=1"]);
+        let header = header(&jar, &url("https://lichess.org/tv")).unwrap();
+        let mut parts: Vec<&str> = header.split("; ").collect();
+        parts.sort();
+        assert_eq!(parts, vec!("lila2=abc", "other=1"));
+    }
+
+    #[test]
+    fn header_domain() {
+        let jar = jar(&["lila2=abc; Domain=.lichess.org"]);
+        assert_eq!(header(&jar, &url("https://lichess.org/")).as_deref(), Some("lila2=abc"));
+        assert_eq!(header(&jar, &url("wss://socket3.lichess.org/")).as_deref(), Some("lila2=abc"));
+        assert_eq!(header(&jar, &url("https://notlichess.org/")), None);
+        assert_eq!(header(&jar, &url("http://localhost:9663/")), None);
+    }
+
+    #[test]
+    fn header_path() {
+        let jar = jar(&["a=1; Path=/api"]);
+        assert_eq!(header(&jar, &url("https://lichess.org/api")).as_deref(), Some("a=1"));
+        assert_eq!(header(&jar, &url("https://lichess.org/api/account")).as_deref(), Some("a=1"));
+        assert_eq!(header(&jar, &url("https://lichess.org/apis")), None);
+        assert_eq!(header(&jar, &url("https://lichess.org/")), None);
+    }
+
+    #[test]
+    fn header_expired() {
+        let jar = jar(&[
+            "old=1; Expires=Wed, 21 Oct 2015 07:28:00 GMT",
+            "deleted=1; Max-Age=0",
+            "new=1; Expires=Fri, 31 Dec 9999 23:59:59 GMT",
+        ]);
+        assert_eq!(header(&jar, &url("https://lichess.org/")).as_deref(), Some("new=1"));
+    }
+
+    #[test]
+    fn header_secure() {
+        let jar = jar(&["lila2=abc; Secure"]);
+        assert_eq!(header(&jar, &url("https://lichess.org/")).as_deref(), Some("lila2=abc"));
+        assert_eq!(header(&jar, &url("wss://socket3.lichess.org/")).as_deref(), Some("lila2=abc"));
+        assert_eq!(header(&jar, &url("http://localhost:9663/")), None);
+    }
+}
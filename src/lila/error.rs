@@ -0,0 +1,76 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use hyper::StatusCode;
+
+/// Everything that can go wrong when talking to lila
+#[derive(Debug)]
+pub enum LilaError {
+    /// Could not connect, or the connection broke
+    Network(Box<dyn Error + Send + Sync>),
+    /// Unexpected response status
+    Status(StatusCode),
+    /// Too many requests, lila asks to wait a minute
+    RateLimited,
+    /// Wrong credentials or token, or the session has expired
+    Auth,
+    /// The response was not what we expected
+    Decode(serde_json::Error),
+}
+
+impl LilaError {
+    pub fn from_status(status: StatusCode) -> LilaError {
+        match status {
+            StatusCode::TOO_MANY_REQUESTS => LilaError::RateLimited,
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => LilaError::Auth,
+            status => LilaError::Status(status),
+        }
+    }
+}
+
+impl fmt::Display for LilaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LilaError::Network(e) => write!(f, "Network error: {}", e),
+            LilaError::Status(status) => write!(f, "Unexpected response: {}", status),
+            LilaError::RateLimited => write!(f, "Rate limited, try again in a minute"),
+            LilaError::Auth => write!(f, "Not authorized"),
+            LilaError::Decode(e) => write!(f, "Could not decode response: {}", e),
+        }
+    }
+}
+
+impl Error for LilaError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            LilaError::Network(e) => Some(e.as_ref()),
+            LilaError::Decode(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<hyper::Error> for LilaError {
+    fn from(e: hyper::Error) -> LilaError {
+        LilaError::Network(Box::new(e))
+    }
+}
+
+impl From<hyper::http::Error> for LilaError {
+    fn from(e: hyper::http::Error) -> LilaError {
+        LilaError::Network(Box::new(e))
+    }
+}
+
+impl From<io::Error> for LilaError {
+    fn from(e: io::Error) -> LilaError {
+        LilaError::Network(Box::new(e))
+    }
+}
+
+impl From<serde_json::Error> for LilaError {
+    fn from(e: serde_json::Error) -> LilaError {
+        LilaError::Decode(e)
+    }
+}
@@ -0,0 +1,101 @@
+use serde_derive::{Deserialize, Serialize};
+
+use std::env;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+#[cfg(unix)]
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+
+/// The parts of a signed in session that are kept between runs,
+/// the cookies are stored in their Set-Cookie form
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct StoredSession {
+    pub server: String,
+    pub user_id: String,
+    pub cookies: Vec<String>,
+}
+
+impl StoredSession {
+    /// $XDG_STATE_HOME/liru/session.json, or ~/.local/state/liru/session.json
+    pub fn default_path() -> Option<PathBuf> {
+        env::var_os("XDG_STATE_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".local").join("state")))
+            .map(|dir| dir.join("liru").join("session.json"))
+    }
+
+    pub fn load(path: &Path) -> Option<StoredSession> {
+        let data = match fs::read_to_string(path) {
+            Ok(data) => data,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return None,
+            Err(e) => {
+                log::warn!("Could not read session {}: {}", path.display(), e);
+                return None;
+            },
+        };
+        serde_json::from_str(&data)
+            .map_err(|e| log::warn!("Invalid session {}: {}", path.display(), e))
+            .ok()
+    }
+
+    /// Writes the session readable only by the current user,
+    /// replacing any previous one
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            let mut builder = fs::DirBuilder::new();
+            builder.recursive(true);
+            #[cfg(unix)]
+            builder.mode(0o700);
+            builder.create(dir)?;
+        }
+        // remove first so the mode applies to a freshly created file
+        StoredSession::delete(path)?;
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+        let mut file = options.open(path)?;
+        file.write_all(serde_json::to_string(self)?.as_bytes())
+    }
+
+    pub fn delete(path: &Path) -> io::Result<()> {
+        match fs::remove_file(path) {
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn save_and_load() {
+        let path = env::temp_dir()
+            .join(format!("liru-test-{}", uuid::Uuid::new_v4()))
+            .join("session.json");
+        let stored = StoredSession {
+            server: "http://localhost:9663".to_owned(),
+            user_id: "flugsio".to_owned(),
+            cookies: vec!("lila2=abc; HttpOnly; Path=/".to_owned()),
+        };
+        stored.save(&path).unwrap();
+        stored.save(&path).unwrap();
+        assert_eq!(StoredSession::load(&path), Some(stored));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        StoredSession::delete(&path).unwrap();
+        assert_eq!(StoredSession::load(&path), None);
+        StoredSession::delete(&path).unwrap();
+        fs::remove_dir(path.parent().unwrap()).unwrap();
+    }
+}
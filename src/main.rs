@@ -2,9 +2,15 @@ use fern;
 use time;
 use time::format_description::well_known::iso8601::Iso8601;
 
+use clap::{Arg, ArgAction, ArgMatches, Command, ErrorKind};
+
+use std::env;
+use std::fs;
 use std::io;
 use std::io::Write;
+use std::process;
 
+mod config;
 mod game;
 mod ui;
 mod lila;
@@ -12,24 +18,197 @@ mod lila;
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
 fn main() {
-    //setup_logger(matches.is_present("debug"));
-    setup_logger(true);
+    let matches = cli().get_matches();
+    setup_logger(log_level(&matches), matches.get_one::<String>("log-file").unwrap())
+        .unwrap_or_else(|e| cli().error(ErrorKind::Io, e).exit());
     log::debug!("Init");
-    let session = setup_session("Press Enter for anonymous");
+    let config = setup_config(&matches)
+        .unwrap_or_else(|e| cli().error(ErrorKind::Io, e).exit());
+    let server = setup_server(&matches, &config)
+        .unwrap_or_else(|e| cli().error(ErrorKind::InvalidValue, e).exit());
+    if matches.get_one::<bool>("logout") == Some(&true) {
+        match lila::Session::logout(server) {
+            Ok(()) => println!("Signed out"),
+            Err(e) => cli().error(ErrorKind::Io, e).exit(),
+        }
+        return;
+    }
+    let token = setup_token(&matches, &config)
+        .unwrap_or_else(|e| cli().error(ErrorKind::Io, e).exit());
+    let session = if matches.get_one::<bool>("anonymous") == Some(&true) {
+        lila::Session::anonymous(server)
+    } else if let Some(token) = token {
+        lila::Session::with_token(server, token)
+            .unwrap_or_else(|e| cli().error(ErrorKind::InvalidValue, e).exit())
+    } else {
+        lila::Session::restore(server.clone())
+            .unwrap_or_else(|| setup_session(server, "Press Enter for anonymous"))
+    };
     let mut tui = ui::TUI::new(session);
+    for target in matches.get_many::<String>("games").into_iter().flatten() {
+        tui.open(target);
+    }
     tui.start();
     log::debug!("Exit");
 }
 
+fn cli() -> Command<'static> {
+    Command::new("liru")
+        .version(VERSION)
+        .about("TUI client for lichess.org and other lila instances")
+        .arg(Arg::new("debug")
+             .long("debug")
+             .short('d')
+             .action(ArgAction::SetTrue)
+             .conflicts_with("quiet")
+             .help("Log debug messages"))
+        .arg(Arg::new("quiet")
+             .long("quiet")
+             .short('q')
+             .action(ArgAction::SetTrue)
+             .help("Only log errors"))
+        .arg(Arg::new("log-file")
+             .long("log-file")
+             .value_name("PATH")
+             .default_value("./liru.log")
+             .help("File to write the log to"))
+        .arg(Arg::new("config")
+             .long("config")
+             .short('c')
+             .value_name("PATH")
+             .help("Config file, defaults to ~/.config/liru/config.json"))
+        .arg(Arg::new("server")
+             .long("server")
+             .value_name("URL")
+             .help("Base url of the lila instance, like https://lichess.org"))
+        .arg(Arg::new("socket-server")
+             .long("socket-server")
+             .value_name("URL")
+             .help("Base url of the lila socket, like wss://socket3.lichess.org"))
+        .arg(Arg::new("anonymous")
+             .long("anonymous")
+             .short('a')
+             .action(ArgAction::SetTrue)
+             .help("Skip sign in"))
+        .arg(Arg::new("token-file")
+             .long("token-file")
+             .value_name("PATH")
+             .help("Read a personal api token from file, instead of signing in"))
+        .arg(Arg::new("token-command")
+             .long("token-command")
+             .value_name("COMMAND")
+             .conflicts_with("token-file")
+             .help("Read a personal api token from the output of a command, like \"pass lichess.org/token\""))
+        .arg(Arg::new("logout")
+             .long("logout")
+             .action(ArgAction::SetTrue)
+             .conflicts_with_all(&["anonymous", "games"])
+             .help("Sign out and forget the stored session"))
+        .arg(Arg::new("games")
+             .value_name("GAME")
+             .multiple_values(true)
+             .help("Game ids, game urls or tv channels (like blitz) to open"))
+}
+
+fn log_level(matches: &ArgMatches) -> log::LevelFilter {
+    if matches.get_one::<bool>("debug") == Some(&true) {
+        log::LevelFilter::Debug
+    } else if matches.get_one::<bool>("quiet") == Some(&true) {
+        log::LevelFilter::Error
+    } else {
+        log::LevelFilter::Warn
+    }
+}
+
+fn setup_config(matches: &ArgMatches) -> Result<config::Config, String> {
+    match matches.get_one::<String>("config") {
+        Some(path) => config::Config::load(path.as_ref(), true),
+        None => match config::Config::default_path() {
+            Some(path) => config::Config::load(&path, false),
+            None => Ok(config::Config::default()),
+        },
+    }
+}
+
+/// Command line options takes precedence over the config,
+/// the socket url is only used together with its server
+fn setup_server(matches: &ArgMatches, config: &config::Config) -> Result<lila::Server, String> {
+    let (url, socket_url) = match matches.get_one::<String>("server") {
+        Some(url) => (Some(url), matches.get_one::<String>("socket-server")),
+        None => (config.server.as_ref(), matches.get_one::<String>("socket-server")
+                 .or(config.socket_server.as_ref())),
+    };
+    match url {
+        Some(url) => lila::Server::new(url, socket_url.map(|s| s.as_str())),
+        None => match socket_url {
+            Some(socket_url) => lila::Server::new(&lila::Server::default().url, Some(socket_url)),
+            None => Ok(lila::Server::default()),
+        },
+    }
+}
+
+/// Reads a token from the first of command line options,
+/// the LIRU_TOKEN environment variable, or the config
+fn setup_token(matches: &ArgMatches, config: &config::Config) -> Result<Option<String>, String> {
+    if let Some(path) = matches.get_one::<String>("token-file") {
+        read_token_file(path).map(Some)
+    } else if let Some(command) = matches.get_one::<String>("token-command") {
+        read_token_command(command).map(Some)
+    } else if let Some(token) = env::var("LIRU_TOKEN").ok().filter(|t| !t.is_empty()) {
+        Ok(Some(token))
+    } else if let Some(ref path) = config.token_file {
+        read_token_file(path).map(Some)
+    } else if let Some(ref command) = config.token_command {
+        read_token_command(command).map(Some)
+    } else {
+        Ok(None)
+    }
+}
+
+fn read_token_file(path: &str) -> Result<String, String> {
+    fs::read_to_string(path)
+        .map_err(|e| format!("Could not read token file {}: {}", path, e))
+        .and_then(|token| first_line(&token))
+}
+
+/// Runs the command in a shell, like password managers
+/// it's expected to print the token on the first line
+fn read_token_command(command: &str) -> Result<String, String> {
+    let output = process::Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(process::Stdio::inherit())
+        .stderr(process::Stdio::inherit())
+        .output()
+        .map_err(|e| format!("Could not run token command: {}", e))?;
+    if !output.status.success() {
+        return Err(format!("Token command failed: {}", output.status));
+    }
+    first_line(&String::from_utf8_lossy(&output.stdout))
+}
+
+fn first_line(token: &str) -> Result<String, String> {
+    match token.lines().next().map(str::trim) {
+        Some(token) if !token.is_empty() => Ok(token.to_owned()),
+        _ => Err("Token is empty".to_owned()),
+    }
+}
+
 /// Recursively asks for valid credentials
-/// or using anonymous with blank username
-fn setup_session(message: &str) -> lila::Session {
+/// or using anonymous with blank username,
+/// signed in sessions are stored for next run
+fn setup_session(server: lila::Server, message: &str) -> lila::Session {
     println!("{}", message);
     match acquire("Username") {
-        ref u if u.is_empty() => lila::Session::anonymous(),
+        ref u if u.is_empty() => lila::Session::anonymous(server),
         username =>
-            lila::Session::sign_in(username, acquire("Password"))
-            .unwrap_or_else(|e| setup_session(e))
+            match lila::Session::sign_in(server.clone(), username, acquire("Password")) {
+                Ok(session) => {
+                    session.save();
+                    session
+                },
+                Err(e) => setup_session(server, &e.to_string()),
+            }
     }
 }
 
@@ -46,12 +225,8 @@ fn acquire(what: &str) -> String {
 }
 
 // levels: trace, debug, info, warn, error
-fn setup_logger(debug: bool) {
-    let level = match debug {
-        true  => log::LevelFilter::Debug,
-        false => log::LevelFilter::Warn,
-    };
-
+fn setup_logger(level: log::LevelFilter, path: &str) -> Result<(), String> {
+    let file = fern::log_file(path).map_err(|e| format!("Could not open log file {}: {}", path, e))?;
     fern::Dispatch::new()
         .format(|out, message, record| {
             let now = time::OffsetDateTime::now_utc();
@@ -63,6 +238,7 @@ fn setup_logger(debug: bool) {
                     message))
         })
         .level(level)
-        .chain(fern::log_file("./liru.log").unwrap())
-        .apply().unwrap();
+        .chain(file)
+        .apply()
+        .map_err(|e| e.to_string())
 }
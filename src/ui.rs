@@ -1,4 +1,6 @@
+mod error_view;
 mod game_view;
+mod loading_view;
 mod menu_option;
 mod menu_result;
 mod menu_view;
@@ -7,7 +9,9 @@ mod renderer;
 mod view;
 mod tui;
 
+use crate::ui::error_view::ErrorView;
 use crate::ui::game_view::GameView;
+use crate::ui::loading_view::LoadingView;
 use crate::ui::menu_option::MenuOption;
 use crate::ui::menu_result::MenuResult;
 use crate::ui::menu_view::MenuView;
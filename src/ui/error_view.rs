@@ -0,0 +1,51 @@
+//! A view in place of one that could not
+//! be opened, explaining what went wrong
+
+use rustbox::Color;
+use rustbox::Key;
+use rustbox::{RB_BOLD, RB_NORMAL};
+
+use crate::lila::LilaError;
+
+use super::MenuResult;
+use super::RBStyle;
+use super::Renderer;
+use super::View;
+
+pub struct ErrorView {
+    name: String,
+    error: LilaError,
+}
+
+impl ErrorView {
+    pub fn new(name: String, error: LilaError) -> ErrorView {
+        ErrorView {
+            name,
+            error,
+        }
+    }
+}
+
+impl View for ErrorView {
+    fn tick(&mut self) {
+    }
+
+    fn render(&self, r: &mut Renderer) {
+        let title = RBStyle { style: RB_BOLD, fg: Color::Red, bg: Color::Black };
+        let text = RBStyle { style: RB_NORMAL, fg: Color::White, bg: Color::Black };
+        r.print(1, 2, title, &format!("Could not open {}", self.name));
+        r.print(1, 4, text, &self.error.to_string());
+    }
+
+    fn name(&self) -> String {
+        self.name.to_owned()
+    }
+
+    fn key_event(&mut self, _key: Key) -> MenuResult {
+        MenuResult::None
+    }
+
+    fn finished(&self) -> bool {
+        true
+    }
+}
@@ -3,7 +3,8 @@ use rustbox::Key;
 use rustbox::{RB_BOLD, RB_NORMAL};
 
 use crate::game;
-use crate::lila;
+use crate::game::movegen;
+use crate::game::san;
 
 use super::MenuResult;
 use super::RBStyle;
@@ -14,25 +15,277 @@ pub struct GameView {
     name: String,
     pub pov: game::ConnectedPov,
     pub input: Vec<char>,
+    error: Option<String>,
+    /// Waiting for y/n before sending
+    confirm: Option<game::Action>,
+    /// A pawn move to the last rank, waiting for a piece
+    promoting: Option<(String, String)>,
 }
 
 impl GameView {
-    pub fn new(session: &lila::Session, name: String, url: String) -> GameView {
-        let connected_pov = game::ConnectedPov::new(session, &url);
-
-        return GameView {
+    pub fn new(name: String, connected_pov: game::ConnectedPov) -> GameView {
+        GameView {
             name: name,
             pov: connected_pov,
             input: vec!(),
+            error: None,
+            confirm: None,
+            promoting: None,
+        }
+    }
+
+    /// Declines whatever the opponent is offering
+    fn decline(&self) -> Option<game::Action> {
+        let pov = self.pov.pov.lock().ok()?;
+        if pov.playing() && pov.opponent.offeringDraw {
+            Some(game::Action::DrawNo)
+        } else if pov.playing() && pov.opponent.proposingTakeback {
+            Some(game::Action::TakebackNo)
+        } else if Self::rematchable(&pov) && pov.opponent.offeringRematch {
+            Some(game::Action::RematchNo)
+        } else {
+            None
+        }
+    }
+
+    /// A rematch is only for the players, once the game is over
+    fn rematchable(pov: &game::Pov) -> bool {
+        !pov.spectator() && pov.game.status.finished()
+    }
+
+    /// Opens the rematch when it has started, otherwise offers one
+    fn rematch(&mut self) -> MenuResult {
+        let (rematch, rematchable) = match self.pov.pov.lock() {
+            Ok(pov) => (pov.game.rematch.clone(), Self::rematchable(&pov)),
+            Err(_) => return MenuResult::None,
         };
+        match rematch {
+            Some(id) => MenuResult::AddGameView { name: id.chars().take(8).collect(), url: id },
+            None => {
+                if rematchable {
+                    self.confirm = Some(game::Action::RematchYes);
+                }
+                MenuResult::None
+            },
+        }
     }
 
     fn handle_input(&mut self) {
-        if self.input.len() == 4 { // assume move for now
-            let from: String = self.input[0..2].iter().cloned().collect();
-            let to: String = self.input[2..4].iter().cloned().collect();
-            self.pov.send_move(from, to);
+        if self.input.is_empty() {
+            return;
+        }
+        let (playing, movable) = self.pov.pov.lock().map_or((false, false), |p| (p.playing(), p.movable()));
+        if !playing {
             self.input.clear();
+            return;
+        }
+        if !movable {
+            return self.queue_premove();
+        }
+        if self.input.contains(&'@') {
+            return self.handle_drop();
+        }
+        let resolved = match self.resolve_input() {
+            Ok(resolved) => resolved,
+            Err(e) => {
+                self.error = Some(e);
+                return;
+            },
+        };
+        let san::Resolved { from, to, promotion } = resolved;
+        if !self.legal(&from, &to) {
+            self.error = Some(format!("{}{} is illegal", from, to));
+            return;
+        }
+        let promotes = self.pov.pov.lock().is_ok_and(|p| p.is_promotion(&from, &to));
+        match promotion {
+            Some(_) if !promotes => {
+                self.error = Some(format!("{}{} is not a promotion", from, to));
+                return;
+            },
+            Some(piece) if self.promotable(piece) => {
+                self.pov.send_move(from, to, Some(piece));
+            },
+            Some(piece) => {
+                self.error = Some(format!("Can't promote to {}", piece));
+                return;
+            },
+            None if promotes => {
+                self.promoting = Some((from, to));
+            },
+            None => self.pov.send_move(from, to, None),
+        }
+        self.input.clear();
+    }
+
+    fn handle_drop(&mut self) {
+        let input: String = self.input.iter().collect();
+        let (role, square) = match san::parse_drop(&input) {
+            Some(drop) => drop,
+            None => {
+                self.error = Some(format!("{} is not a drop", input));
+                return;
+            },
+        };
+        if let Err(e) = self.droppable(role, square) {
+            self.error = Some(e);
+            return;
+        }
+        self.pov.send_drop(role, square.to_string());
+        self.input.clear();
+    }
+
+    /// Whether the piece is in the player's pocket and may go on the square,
+    /// drops that don't stop a check are left to lila
+    fn droppable(&self, role: game::Role, square: game::Square) -> Result<(), String> {
+        let pov = self.pov.pov.lock().map_err(|e| e.to_string())?;
+        if pov.game.variant.key != "crazyhouse" {
+            return Err("Drops are only for crazyhouse".to_owned());
+        }
+        let board = pov.board()?;
+        if board.pockets.as_ref().is_none_or(|p| p.count(pov.player.color, role) == 0) {
+            return Err(format!("No {} in the pocket", role.name()));
+        }
+        if board.get(square).is_some() {
+            return Err(format!("{} is taken", square));
+        }
+        if role == game::Role::Pawn && (square.rank == 0 || square.rank == 7) {
+            return Err("Pawns can't go on the first or last rank".to_owned());
+        }
+        Ok(())
+    }
+
+    /// Premoves are only taken as uci, the position they are for isn't known
+    /// yet. Pawns reaching the last rank become queens unless typed otherwise.
+    /// Typed after an opponent move, like d7d5 e4d5, it only answers that move.
+    fn queue_premove(&mut self) {
+        let (after, reply) = match self.input.iter().position(|&c| c == ' ') {
+            Some(i) => (Some(&self.input[..i]), &self.input[i + 1..]),
+            None => (None, self.input.as_slice()),
+        };
+        let after = match after.map(Self::parse_uci) {
+            Some(Some(san::Resolved { from, to, promotion })) => {
+                Some(format!("{}{}{}", from, to, promotion.map_or(String::new(), |p| p.to_string())))
+            },
+            Some(None) => {
+                self.error = Some("Conditional premoves are typed like d7d5 e4d5".to_owned());
+                return;
+            },
+            None => None,
+        };
+        let san::Resolved { from, to, promotion } = match Self::parse_uci(reply) {
+            Some(resolved) => resolved,
+            None => {
+                self.error = Some("Premoves are typed like e2e4".to_owned());
+                return;
+            },
+        };
+        let (own, promotes) = match self.pov.pov.lock() {
+            Ok(pov) => {
+                let own = pov.board().ok()
+                    .and_then(|b| b.get(game::Square::parse(&from)?))
+                    .is_some_and(|p| p.color == pov.player.color);
+                (own, pov.is_promotion(&from, &to))
+            },
+            Err(_) => return,
+        };
+        if !own {
+            self.error = Some(format!("No piece of yours on {}", from));
+            return;
+        }
+        if promotion.is_some() && !promotes {
+            self.error = Some(format!("{}{} is not a promotion", from, to));
+            return;
+        }
+        if promotion.is_some_and(|p| !self.promotable(p)) {
+            self.error = Some(format!("Can't promote to {}", promotion.unwrap_or(' ')));
+            return;
+        }
+        let promotion = promotion.or(if promotes { Some('q') } else { None });
+        self.pov.set_premove(game::Premove { from, to, promotion, after });
+        self.input.clear();
+    }
+
+    fn parse_uci(input: &[char]) -> Option<san::Resolved> {
+        let square = |f: char, r: char| ('a'..='h').contains(&f) && ('1'..='8').contains(&r);
+        match input {
+            &[f1, r1, f2, r2, ref promotion @ ..] if square(f1, r1) && square(f2, r2) && promotion.len() < 2 => {
+                Some(san::Resolved {
+                    from: format!("{}{}", f1, r1),
+                    to: format!("{}{}", f2, r2),
+                    promotion: promotion.first().map(|p| p.to_ascii_lowercase()),
+                })
+            },
+            _ => None,
+        }
+    }
+
+    /// Takes uci like e2e4 or e7e8q as is, anything else as san
+    fn resolve_input(&self) -> Result<san::Resolved, String> {
+        let input: String = self.input.iter().collect();
+        match Self::parse_uci(&self.input) {
+            Some(resolved) => Ok(resolved),
+            None => {
+                let pov = self.pov.pov.lock().map_err(|e| e.to_string())?;
+                let board = pov.board()?;
+                let resolved = match pov.dests() {
+                    Some(dests) => san::resolve_dests(&board, dests, &input),
+                    None if pov.standard_rules() => san::resolve(&board, &input),
+                    None => return Err(format!("Type moves like e2e4 in {}", pov.game.variant.name)),
+                };
+                resolved.map_err(|e| format!("{} is {}", input, e))
+            },
+        }
+    }
+
+    fn legal(&self, from: &str, to: &str) -> bool {
+        self.pov.pov.lock().map_or(true, |p| p.legal(from, to))
+    }
+
+    /// Where the piece on the square typed so far can go
+    fn targets(&self, pov: &game::Pov) -> Vec<game::Square> {
+        let input: String = self.input.iter().collect();
+        if let Some(dests) = pov.dests() {
+            return dests.targets(&input).iter().filter_map(|s| game::Square::parse(s)).collect();
+        }
+        match (pov.checked_board(), game::Square::parse(&input)) {
+            (Some(board), Some(from)) => movegen::moves_from(&board, from).iter()
+                .flat_map(|m| vec!(m.to, m.king_to()))
+                .collect(),
+            _ => vec!(),
+        }
+    }
+
+    /// Spectators and finished games take no moves
+    fn playing(&self) -> bool {
+        self.pov.pov.lock().is_ok_and(|p| p.playing())
+    }
+
+    /// Takes the pov rather than locking it, as rendering holds the lock
+    fn antichess(pov: &game::Pov) -> bool {
+        pov.game.variant.key == "antichess"
+    }
+
+    fn promotable(&self, piece: char) -> bool {
+        match piece.to_ascii_lowercase() {
+            'k' => self.pov.pov.lock().is_ok_and(|p| Self::antichess(&p)),
+            p => game::promotion_role(p).is_some(),
+        }
+    }
+
+    fn pick_promotion(&mut self, key: Key) {
+        let (from, to) = match self.promoting.take() {
+            Some(promoting) => promoting,
+            None => return,
+        };
+        match key {
+            Key::Char(piece) if self.promotable(piece) => {
+                self.pov.send_move(from, to, Some(piece.to_ascii_lowercase()));
+            },
+            Key::Esc => {
+                self.input = format!("{}{}", from, to).chars().collect();
+            },
+            _ => self.promoting = Some((from, to)),
         }
     }
 
@@ -41,6 +294,21 @@ impl GameView {
         r.print(x, y+13, style, &format!("{:3}ms", latency.average()));
     }
 
+    pub fn render_status(&self, r: &mut Renderer, x: usize, y: usize, status: game::socket::Status) {
+        let style = RBStyle { style: RB_BOLD, fg: Color::Red, bg: Color::Black };
+        let text = match status {
+            game::socket::Status::Connected => String::new(),
+            game::socket::Status::Connecting => "Connecting...".to_owned(),
+            game::socket::Status::Reconnecting { attempt } => format!("Reconnecting ({})...", attempt),
+        };
+        r.print(x, y, style, &format!("{:20}", text));
+    }
+
+    pub fn render_notice(&self, r: &mut Renderer, x: usize, y: usize, notice: Option<&game::Notice>) {
+        let style = RBStyle { style: RB_BOLD, fg: Color::Green, bg: Color::Black };
+        r.print(x, y, style, &format!("{:50}", notice.map_or("", |n| n.text.as_str())));
+    }
+
     pub fn render_pov(&self, r: &mut Renderer, x: usize, y: usize, pov: &game::Pov) {
         if let Some(crowd) = &pov.crowd {
             let opponent_present = crowd.opponent_from(pov.orientation());
@@ -52,20 +320,87 @@ impl GameView {
             self.render_player(r, x + 1, y + 2, &pov.opponent, true);
             self.render_player(r, x + 1, y + 14, &pov.player, true);
         }
-        let fen = pov.game.fen.clone();
-        self.render_fen(r, x, y, fen, pov.orientation() == game::Color::white, &pov.game);
+        match pov.board() {
+            Ok(board) => {
+                let mut highlights: Vec<(game::Square, Color)> = pov.game.variant.goal().into_iter()
+                    .map(|s| (s, Color::Cyan))
+                    .collect();
+                highlights.extend(Self::uci_squares(pov.game.lastMove.as_deref(), Color::Magenta));
+                highlights.extend(pov.explosion.iter().map(|&s| (s, Color::Red)));
+                // conditional premoves may never be played, only the sure one shows
+                let premove = self.pov.premoves().into_iter().find(|p| p.after.is_none()).map(|p| p.uci());
+                highlights.extend(Self::uci_squares(premove.as_deref(), Color::White));
+                highlights.extend(self.targets(pov).into_iter().map(|s| (s, Color::Green)));
+                self.render_board(r, x, y, &board, pov.orientation() == game::Color::white, &highlights);
+                if pov.game.variant.key == "threeCheck" {
+                    self.render_checks(r, x + 26, y + 9, &pov.opponent);
+                    self.render_checks(r, x + 26, y + 10, &pov.player);
+                }
+                if let Some(pockets) = &board.pockets {
+                    self.render_pocket(r, x + 26, y + 9, pockets, !pov.orientation());
+                    self.render_pocket(r, x + 26, y + 10, pockets, pov.orientation());
+                }
+                if pov.checked_board().is_some() {
+                    self.render_situation(r, x + 26, y + 11, movegen::situation(&board));
+                }
+            },
+            Err(e) => {
+                let error = RBStyle { style: RB_NORMAL, fg: Color::Red, bg: Color::Black };
+                r.print(x + 5, y + 4, error, &e);
+            },
+        }
         match pov.clock {
             Some(ref clock) => {
-                self.render_clock(r, x + 19, y + 3, clock.from(!pov.orientation()));
-                self.render_clock(r, x + 19, y + 12, clock.from(pov.orientation()));
+                self.render_clock(r, x + 17, y + 3, clock, !pov.orientation());
+                self.render_clock(r, x + 17, y + 12, clock, pov.orientation());
             },
             None => ()
         };
-        if pov.movable() {
+        if self.promoting.is_some() {
+            let style = RBStyle { style: RB_BOLD, fg: Color::Yellow, bg: Color::Black };
+            let pieces = if Self::antichess(pov) { "q/r/b/n/k" } else { "q/r/b/n" };
+            r.print(5, 16, style, &format!("Promote to {}, Esc cancels{:10}", pieces, ""));
+        } else if let Some(action) = self.confirm {
+            let style = RBStyle { style: RB_BOLD, fg: Color::Yellow, bg: Color::Black };
+            r.print(5, 16, style, &format!("{} y/n{:20}", action.question(), ""));
+        } else if pov.playing() {
             let style = RBStyle { style: RB_BOLD, fg: Color::White, bg: Color::Black };
-            r.print(5, 16, style, &format!("Move {}▍          ", self.input.iter().cloned().collect::<String>()));
+            let prompt = if pov.movable() { "Move" } else { "Premove" };
+            r.print(5, 16, style, &format!("{} {}▍          ", prompt, self.input.iter().cloned().collect::<String>()));
+            let queued = RBStyle { style: RB_NORMAL, fg: Color::Green, bg: Color::Black };
+            let premoves: Vec<String> = self.pov.premoves().iter()
+                .map(|p| p.after.as_ref().map_or(p.uci(), |after| format!("{} {}", after, p.uci())))
+                .collect();
+            let premoves = if premoves.is_empty() { String::new() } else { format!("Premove {}, Esc cancels", premoves.join(", ")) };
+            r.print(5, 18, queued, &format!("{:40}", premoves));
+            let error = RBStyle { style: RB_NORMAL, fg: Color::Red, bg: Color::Black };
+            r.print(5, 17, error, &format!("{:30}", self.error.as_deref().unwrap_or("")));
         }
         self.render_last_move(r, x + 26, y + 12, &pov.game);
+        self.render_pending(r, x + 26, y + 3, &pov.pending());
+        if let Some(mut outcome) = pov.game.status.outcome(pov.game.winner) {
+            if let (true, Some(reason)) = (pov.game.status.name == "variantEnd", pov.game.variant.end_reason()) {
+                outcome.reason = reason.to_owned();
+            }
+            self.render_outcome(r, x + 26, y + 6, &outcome);
+        }
+        let time_control = pov.clock.as_ref().and_then(|c| c.time_control());
+        self.render_variant(r, x + 26, y + 2, &pov.game.variant, time_control);
+    }
+
+    pub fn render_pending(&self, r: &mut Renderer, x: usize, y: usize, pending: &[String]) {
+        let style = RBStyle { style: RB_BOLD, fg: Color::Magenta, bg: Color::Black };
+        for i in 0..3 {
+            r.print(x, y + i, style, &format!("{:30}", pending.get(i).map_or("", |p| p.as_str())));
+        }
+    }
+
+    pub fn render_outcome(&self, r: &mut Renderer, x: usize, y: usize, outcome: &game::Outcome) {
+        let banner = RBStyle { style: RB_BOLD, fg: Color::Black, bg: Color::White };
+        let style = RBStyle { style: RB_NORMAL, fg: Color::White, bg: Color::Black };
+        r.print(x, y, banner, &format!(" {} ", outcome.score));
+        r.print(x, y + 1, style, &outcome.result);
+        r.print(x, y + 2, style, &outcome.reason);
     }
 
     pub fn render_player(&self, r: &mut Renderer, x: usize, y: usize, player: &game::Player, present: bool) {
@@ -75,6 +410,11 @@ impl GameView {
         match player.user {
             Some(ref user) => {
                 r.print(x + 5, y,  if present { style } else { style_absent }, &format!("{}", user.username));
+                if let Some(diff) = player.ratingDiff {
+                    let fg = if diff < 0 { Color::Red } else { Color::Green };
+                    let diff_style = RBStyle { style: RB_NORMAL, fg, bg: Color::Black };
+                    r.print(x + 6 + user.username.chars().count(), y, diff_style, &format!("{:+}", diff));
+                }
             },
             None => ()
         };
@@ -103,33 +443,70 @@ impl GameView {
         r.print(x, y, color, &format!("{}           ", game.lastMoveSan.as_ref().or(game.lastMove.as_ref()).unwrap_or(&"".to_string())));
     }
 
-    pub fn render_clock(&self, r: &mut Renderer, x: usize, y: usize, time: f64) {
-        r.print(x, y, RBStyle { style: RB_BOLD, fg: if time < 10f64 { Color::Red } else { Color::White }, bg: Color::Black }, &format!("{:04.1}", time));
+    /// Time control, variant and how it is won
+    pub fn render_variant(&self, r: &mut Renderer, x: usize, y: usize, variant: &game::Variant, time_control: Option<String>) {
+        let name = RBStyle { style: RB_BOLD, fg: Color::White, bg: Color::Black };
+        let style = RBStyle { style: RB_NORMAL, fg: Color::White, bg: Color::Black };
+        let title = match time_control {
+            Some(time_control) => format!("{} {}", time_control, variant.name),
+            None => variant.name.clone(),
+        };
+        r.print(x, y, name, &title);
+        r.print(x + title.chars().count() + 1, y, style, variant.win_condition());
     }
 
-    fn highlighed(uci: &Option<String>, x: usize, y: usize) -> bool {
-        if let Some(uci) = uci {
-            let uci = uci
-                .replace("a", "1")
-                .replace("b", "2")
-                .replace("c", "3")
-                .replace("d", "4")
-                .replace("e", "5")
-                .replace("f", "6")
-                .replace("g", "7")
-                .replace("h", "8");
-            if uci[0..2] == format!("{}{}", x, y) || uci[2..4] == format!("{}{}", x, y) {
-                return true;
-            }
-        }
-        false
+    /// Checks still to give in three-check
+    pub fn render_checks(&self, r: &mut Renderer, x: usize, y: usize, player: &game::Player) {
+        let style = if player.color == game::Color::white {
+            RBStyle { style: RB_NORMAL, fg: Color::Yellow, bg: Color::Black }
+        } else {
+            RBStyle { style: RB_BOLD, fg: Color::Blue, bg: Color::Black }
+        };
+        let left = game::CHECKS_TO_WIN.saturating_sub(player.checks);
+        r.print(x, y, style, &format!("{} checks to win{:5}", left, ""));
+    }
+
+    pub fn render_pocket(&self, r: &mut Renderer, x: usize, y: usize, pockets: &game::Pockets, color: game::Color) {
+        let style = if color == game::Color::white {
+            RBStyle { style: RB_NORMAL, fg: Color::Yellow, bg: Color::Black }
+        } else {
+            RBStyle { style: RB_BOLD, fg: Color::Blue, bg: Color::Black }
+        };
+        let roles = [game::Role::Pawn, game::Role::Knight, game::Role::Bishop, game::Role::Rook, game::Role::Queen];
+        let pieces: Vec<String> = roles.iter()
+            .map(|&role| (role, pockets.count(color, role)))
+            .filter(|&(_, count)| count > 0)
+            .map(|(role, count)| format!("{}{}", role.char().to_ascii_uppercase(), count))
+            .collect();
+        r.print(x, y, style, &format!("{:20}", pieces.join(" ")));
     }
 
-    pub fn render_fen(&self, r: &mut Renderer, x: usize, y: usize, fen: String, orientation: bool, game: &game::Game) {
-        let _text_style  = RBStyle { style: RB_BOLD, fg: Color::White, bg:    Color::Black };
+    pub fn render_situation(&self, r: &mut Renderer, x: usize, y: usize, situation: movegen::Situation) {
+        let style = RBStyle { style: RB_BOLD, fg: Color::Red, bg: Color::Black };
+        let text = match situation {
+            movegen::Situation::Normal => "",
+            movegen::Situation::Check => "Check",
+            movegen::Situation::Checkmate => "Checkmate",
+            movegen::Situation::Stalemate => "Stalemate",
+        };
+        r.print(x, y, style, &format!("{:10}", text));
+    }
+
+    pub fn render_clock(&self, r: &mut Renderer, x: usize, y: usize, clock: &game::Clock, color: game::Color) {
+        let fg = if clock.emergency(color) { Color::Red } else { Color::White };
+        r.print(x, y, RBStyle { style: RB_BOLD, fg, bg: Color::Black }, &format!("{:>7}", game::clock::format(clock.from(color))));
+    }
+
+    /// Both squares of a move, to highlight them
+    fn uci_squares(uci: Option<&str>, bg: Color) -> Vec<(game::Square, Color)> {
+        let squares = uci.map_or(vec!(), |uci| vec!(uci.get(0..2), uci.get(2..4)));
+        squares.into_iter().flatten().filter_map(game::Square::parse).map(|s| (s, bg)).collect()
+    }
+
+    /// Highlighted squares get their color as background, later ones win
+    pub fn render_board(&self, r: &mut Renderer, x: usize, y: usize, board: &game::Board, orientation: bool, highlights: &[(game::Square, Color)]) {
         let border      = RBStyle { style: RB_NORMAL, fg: Color::Cyan, bg:   Color::Black };
 
-        //r.print(x + 3, y +  1, text_style, &fen);
         r.print(x + 5, y +  3, border, "╔═════════════════╗");
         r.print(x + 5, y + 12, border, "╚═════════════════╝");
         if orientation {
@@ -138,56 +515,27 @@ impl GameView {
             r.print(x + 7, y + 13, border,   "h g f e d c b a");
         }
 
-        // TODO: fen parser
-        // r1bqkb1r/ppp1pppp/2n2n2/3p4/4P3/3P1P2/PPP3PP/RNBQKBNR w KQkq - 1 4
-        let mut fen = fen.split(' ').next().unwrap().to_string();
-        if !orientation {
-            fen = fen.chars().rev().collect();
-        }
-        for (y2, row) in fen.split('/').enumerate() {
-            if y2 >= 8 {
-                break; // temp workaround for crazyhouse
-            }
-            let row = row
-                .replace("8", "········")
-                .replace("7", "·······")
-                .replace("6", "······")
-                .replace("5", "·····")
-                .replace("4", "····")
-                .replace("3", "···")
-                .replace("2", "··")
-                .replace("1", "·");
-            if orientation {
-                r.print(x + 3, 4 + y + y2, border, &format!("{} ║", 9-(y2+1)));
-            } else {
-                r.print(x + 3, 4 + y + y2, border, &format!("{} ║", y2+1));
-            }
+        for y2 in 0..8 {
+            let rank = if orientation { 7 - y2 } else { y2 };
+            r.print(x + 3, 4 + y + y2, border, &format!("{} ║", rank + 1));
             r.print(x + 23, 4 + y + y2, border, "║");
-            for (x2, char) in row.chars().enumerate() {
-                let (uci_x, uci_y) = if orientation {
-                    (x2 + 1, 9 - (y2 + 1))
-                } else {
-                    (9 - (x2 + 1), y2 + 1)
-                };
-                let bg = if Self::highlighed(&game.lastMove, uci_x, uci_y) {
-                    Color::Magenta
-                } else {
-                    Color::Black
-                };
-                let color = if char == '·' {
-                    if (y2 + x2) % 2 == 0 {
-                        RBStyle { style: RB_NORMAL, fg: Color::Yellow, bg: bg }
-                    } else {
-                        RBStyle { style: RB_BOLD, fg: Color::Blue, bg: bg }
-                    }
-                } else {
-                    if char.is_uppercase() {
-                        RBStyle { style: RB_NORMAL, fg: Color::Yellow, bg: bg }
-                    } else {
-                        RBStyle { style: RB_BOLD, fg: Color::Blue, bg: bg }
-                    }
+            for x2 in 0..8 {
+                let file = if orientation { x2 } else { 7 - x2 };
+                let square = game::Square::new(file as u8, rank as u8);
+                let bg = highlights.iter().rev()
+                    .find(|&&(s, _)| s == square)
+                    .map_or(Color::Black, |&(_, bg)| bg);
+                let (style, char) = match board.get(square) {
+                    Some(piece) if piece.color == game::Color::white => {
+                        (RBStyle { style: RB_NORMAL, fg: Color::Yellow, bg }, piece.role.char().to_ascii_uppercase())
+                    },
+                    Some(piece) => {
+                        (RBStyle { style: RB_BOLD, fg: Color::Blue, bg }, piece.role.char().to_ascii_uppercase())
+                    },
+                    None if (y2 + x2) % 2 == 0 => (RBStyle { style: RB_NORMAL, fg: Color::Yellow, bg }, '·'),
+                    None => (RBStyle { style: RB_BOLD, fg: Color::Blue, bg }, '·'),
                 };
-                r.print(7 + x + x2*2, 4 + y + y2, color, &char.to_uppercase().collect::<String>());
+                r.print(7 + x + x2*2, 4 + y + y2, style, &char.to_string());
             }
         }
     }
@@ -198,10 +546,21 @@ impl View for GameView {
         self.pov.pov.lock().ok().map(|mut p| {
             p.tick();
         });
+        self.pov.tick();
+        if let Some(rejected) = self.pov.take_rejected() {
+            self.error = Some(format!("{} was {}", rejected.uci, rejected.reason));
+            self.input = rejected.uci.chars().collect();
+        }
     }
 
     fn render(&self, r: &mut Renderer) {
         self.pov.latency.lock().ok().map(|l| self.render_latency(r, 0, 0, &l));
+        if let Ok(status) = self.pov.status.lock() {
+            self.render_status(r, 1, 1, *status);
+        }
+        if let Ok(notice) = self.pov.notice.lock() {
+            self.render_notice(r, 22, 1, notice.as_ref().filter(|n| n.visible()));
+        }
         self.pov.pov.lock().ok().map(|p| self.render_pov(r, 0, 0, &p));
     }
 
@@ -210,18 +569,51 @@ impl View for GameView {
     }
 
     fn key_event(&mut self, key: Key) -> MenuResult {
+        if self.promoting.is_some() {
+            self.pick_promotion(key);
+            return MenuResult::None;
+        }
+        if let Some(action) = self.confirm.take() {
+            if let Key::Char('y') = key {
+                self.pov.send_action(action);
+            }
+            return MenuResult::None;
+        }
         match key {
+            Key::Ctrl('a') if self.playing() => self.confirm = Some(game::Action::Abort),
+            Key::Ctrl('r') if self.playing() => self.confirm = Some(game::Action::Resign),
+            Key::Ctrl('d') if self.playing() => self.confirm = Some(game::Action::DrawYes),
+            Key::Ctrl('t') if self.playing() => self.confirm = Some(game::Action::TakebackYes),
+            Key::Ctrl('e') if self.playing() => self.confirm = Some(game::Action::Moretime),
+            Key::Ctrl('v') if self.playing() => self.confirm = Some(game::Action::ResignForce),
+            Key::Ctrl('n') => self.confirm = self.decline(),
+            Key::Ctrl('o') => return self.rematch(),
             Key::Enter => {
+                self.error = None;
                 self.handle_input();
             }
-            Key::Char(x) => {
+            Key::Char(x) if self.playing() => {
+                self.error = None;
                 self.input.push(x);
             }
+            Key::Esc => {
+                self.input.clear();
+                self.pov.cancel_premove();
+            }
             Key::Backspace => {
+                self.error = None;
                 self.input.pop();
             }
             _ => ()
         }
         MenuResult::None
     }
+
+    fn finished(&self) -> bool {
+        self.pov.pov.lock().is_ok_and(|p| p.game.status.finished())
+    }
+
+    fn capturing(&self) -> bool {
+        !self.input.is_empty() || self.promoting.is_some() || self.confirm.is_some()
+    }
 }
@@ -0,0 +1,69 @@
+//! A view shown while a game is fetched in the background,
+//! replaced by the game or an error when it's done
+
+use rustbox::Color;
+use rustbox::Key;
+use rustbox::RB_NORMAL;
+
+use std::sync::mpsc;
+
+use crate::game::ConnectedPov;
+use crate::lila;
+use crate::lila::LilaError;
+
+use super::ErrorView;
+use super::GameView;
+use super::MenuResult;
+use super::RBStyle;
+use super::Renderer;
+use super::View;
+
+pub struct LoadingView {
+    name: String,
+    loaded_rx: mpsc::Receiver<Result<ConnectedPov, LilaError>>,
+}
+
+impl LoadingView {
+    pub fn new(session: &lila::Session, name: String, url: String) -> LoadingView {
+        let (loaded_tx, loaded_rx) = mpsc::channel();
+        let session_1 = session.clone();
+        session.spawn(async move {
+            let result = ConnectedPov::connect(&session_1, &url).await;
+            // the view may already be closed
+            let _ = loaded_tx.send(result);
+        });
+        LoadingView {
+            name,
+            loaded_rx,
+        }
+    }
+}
+
+impl View for LoadingView {
+    fn tick(&mut self) {
+    }
+
+    fn render(&self, r: &mut Renderer) {
+        let style = RBStyle { style: RB_NORMAL, fg: Color::White, bg: Color::Black };
+        r.print(1, 2, style, &format!("Loading {}...", self.name));
+    }
+
+    fn name(&self) -> String {
+        self.name.to_owned()
+    }
+
+    fn key_event(&mut self, _key: Key) -> MenuResult {
+        MenuResult::None
+    }
+
+    fn loaded(&mut self) -> Option<Box<dyn View>> {
+        match self.loaded_rx.try_recv() {
+            Ok(Ok(pov)) => Some(Box::new(GameView::new(self.name.clone(), pov))),
+            Ok(Err(e)) => {
+                log::error!("Could not open {}: {}", self.name, e);
+                Some(Box::new(ErrorView::new(self.name.clone(), e)))
+            },
+            Err(_) => None,
+        }
+    }
+}
@@ -8,7 +8,41 @@ pub enum MenuOption {
     },
 }
 
+/// Channel keys as used in the tv urls
+const TV_CHANNELS: [&str; 13] = [
+    "best", "bullet", "blitz", "classical", "crazyhouse", "chess960",
+    "kingOfTheHill", "threeCheck", "antichess", "atomic", "horde",
+    "racingKings", "computer",
+];
+
 impl MenuOption {
+    /// Interprets a game id, full game url or tv channel
+    /// given by the user, for example on the command line
+    pub fn from_target(target: &str) -> MenuOption {
+        let path = match target.find("://") {
+            Some(i) => target[i + 3..].split_once('/').map_or("", |(_, path)| path),
+            None => target,
+        };
+        let path = path.trim_matches('/');
+        let channel = path.strip_prefix("tv/").unwrap_or(path);
+        match TV_CHANNELS.iter().find(|c| c.eq_ignore_ascii_case(channel)) {
+            Some(channel) => MenuOption::WatchTv {
+                name: channel.to_string(),
+                url: format!("tv/{}", channel),
+            },
+            None => {
+                // strip things like /black or #32 from shared links
+                let id: String = path.chars()
+                    .take_while(|c| c.is_ascii_alphanumeric())
+                    .collect();
+                MenuOption::WatchTv {
+                    name: id.chars().take(8).collect(),
+                    url: id,
+                }
+            },
+        }
+    }
+
     pub fn name(&self) -> &str {
         match self {
             &MenuOption::WatchTv {ref name, ..} => { // TODO remove url
@@ -26,3 +60,34 @@ impl MenuOption {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn url(option: MenuOption) -> String {
+        match option {
+            MenuOption::WatchTv { url, .. } => url,
+        }
+    }
+
+    #[test]
+    fn from_target_channel() {
+        assert_eq!(url(MenuOption::from_target("blitz")), "tv/blitz");
+        assert_eq!(url(MenuOption::from_target("racingkings")), "tv/racingKings");
+        assert_eq!(url(MenuOption::from_target("tv/horde")), "tv/horde");
+    }
+
+    #[test]
+    fn from_target_game() {
+        assert_eq!(url(MenuOption::from_target("q7ZvsdUF")), "q7ZvsdUF");
+        assert_eq!(url(MenuOption::from_target("q7ZvsdUFab12")), "q7ZvsdUFab12");
+        assert_eq!(url(MenuOption::from_target("https://lichess.org/q7ZvsdUF/black#32")), "q7ZvsdUF");
+    }
+
+    #[test]
+    fn from_target_name() {
+        assert_eq!(MenuOption::from_target("q7ZvsdUFab12").name(), "q7ZvsdUF");
+        assert_eq!(MenuOption::from_target("https://lichess.org/tv/bullet").name(), "bullet");
+    }
+}
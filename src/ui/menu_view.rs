@@ -65,6 +65,10 @@ impl View for MenuView {
     fn tick(&mut self) {
     }
 
+    fn closable(&self) -> bool {
+        false
+    }
+
     fn render(&self, r: &mut Renderer) {
         let dark  = RBStyle { style: RB_BOLD, fg: Color::Blue, bg:     Color::Black };
         let light = RBStyle { style: RB_NORMAL, fg: Color::Yellow, bg: Color::Black };
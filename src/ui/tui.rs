@@ -10,7 +10,8 @@ use std::time::Duration;
 
 use crate::lila;
 
-use super::GameView;
+use super::LoadingView;
+use super::MenuOption;
 use super::MenuResult;
 use super::MenuView;
 use super::RBStyle;
@@ -52,10 +53,21 @@ impl TUI {
     }
 
     fn add_game(&mut self, name: String, url: String) {
-        let game = GameView::new(&self.session, name, url);
+        let game = LoadingView::new(&self.session, name, url);
         self.add_view(Box::new(game) as Box<dyn View>);
     }
 
+    /// Opens a game or tv channel in a new view, see `MenuOption::from_target`
+    pub fn open(&mut self, target: &str) {
+        match MenuOption::from_target(target).execute() {
+            MenuResult::AddGameView { name, url } => {
+                self.add_game(name, url);
+                self.current_view = self.views.len() - 1;
+            },
+            MenuResult::None => {},
+        }
+    }
+
     pub fn start(&mut self) {
         while self.running {
             self.tick();
@@ -78,6 +90,34 @@ impl TUI {
         }
     }
 
+    /// Closes the current tab, dropping a game closes its socket too
+    fn close_view(&mut self) {
+        if self.views[self.current_view].closable() {
+            self.views.remove(self.current_view);
+            self.current_view = self.current_view.min(self.views.len() - 1);
+            self.renderer.clear();
+        }
+    }
+
+    /// Closes every tab with a game that is over
+    fn close_finished(&mut self) {
+        let current = self.current_view;
+        let mut index = 0;
+        let mut closed_before = 0;
+        self.views.retain(|view| {
+            let close = view.closable() && view.finished();
+            if close && index <= current {
+                closed_before += 1;
+            }
+            index += 1;
+            !close
+        });
+        if closed_before > 0 {
+            self.current_view = current.saturating_sub(closed_before).min(self.views.len() - 1);
+            self.renderer.clear();
+        }
+    }
+
     fn current_view(&mut self) -> &mut Box<dyn View> {
         self.views.get_mut(self.current_view).unwrap()
     }
@@ -86,9 +126,11 @@ impl TUI {
         match self.renderer.rb.peek_event(Duration::from_millis(100), false) {
             Ok(rustbox::Event::KeyEvent(key)) => {
                 match key {
-                    Key::Char('q') => self.running = false,
+                    Key::Char('q') if !self.current_view().capturing() => self.running = false,
                     Key::Left => self.prev_view(),
                     Key::Right => self.next_view(),
+                    Key::Ctrl('w') => self.close_view(),
+                    Key::Ctrl('x') => self.close_finished(),
                     key => {
                         match self.current_view().key_event(key) {
                             MenuResult::AddGameView { name, url } => {
@@ -104,8 +146,15 @@ impl TUI {
         }
     }
 
+    /// Ticks every view, since games in the background
+    /// may need to resend moves too
     pub fn tick(&mut self) {
-        self.views.get_mut(self.current_view).unwrap().tick();
+        for view in self.views.iter_mut() {
+            if let Some(loaded) = view.loaded() {
+                *view = loaded;
+            }
+            view.tick();
+        }
     }
 
     pub fn render(&mut self) {
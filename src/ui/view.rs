@@ -8,4 +8,24 @@ pub trait View {
     fn name(&self) -> String;
     fn render(&self, ui: &mut Renderer);
     fn key_event(&mut self, key: rustbox::keyboard::Key) -> MenuResult;
+
+    /// The view to replace this one with, when it's done loading
+    fn loaded(&mut self) -> Option<Box<dyn View>> {
+        None
+    }
+
+    /// Whether the tab can be closed, menus stay open
+    fn closable(&self) -> bool {
+        true
+    }
+
+    /// Whether there is nothing left to happen, so it can be closed in bulk
+    fn finished(&self) -> bool {
+        false
+    }
+
+    /// Whether all typed keys are input, like while typing a move
+    fn capturing(&self) -> bool {
+        false
+    }
 }